@@ -1,10 +1,28 @@
+use crate::unicorn::matcher::{glob_match, normalize_compatible};
 use alloc::string::String;
 use alloc::vec::Vec;
+use glenda::error::Error;
+use glenda::protocol::device::LogicDeviceType;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Manifest {
     pub drivers: Vec<DriverEntry>,
+    #[serde(default)]
+    pub require_platform: bool,
+    #[serde(default = "default_slot_quota")]
+    pub slot_quota: usize,
+    #[serde(default)]
+    pub aliases: Vec<AliasEntry>,
+    // Physical windows device registers may live in. Empty trusts every region.
+    #[serde(default)]
+    pub io_space: Vec<IoWindow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct IoWindow {
+    pub base: usize,
+    pub size: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -12,10 +30,157 @@ pub struct DriverEntry {
     pub name: String,
     pub binary: String,
     pub compatible: Vec<String>,
+    #[serde(default)]
+    pub pci_ids: Vec<String>, // "VVVV:DDDD", either half may be "*"
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: usize,
+    // `compatible` in matching form, filled by `normalize`.
+    #[serde(skip)]
+    pub compatible_norm: Vec<String>,
+}
+
+impl DriverEntry {
+    pub fn normalize(&mut self) {
+        self.compatible_norm = self.compatible.iter().map(|c| normalize_compatible(c)).collect();
+    }
+}
+
+/// A stable name for whichever logical device matches this boot. Every given
+/// field must match; `compatible` is a glob over the device name and the
+/// compatible strings of its driver's node, `parent` one over its parent_name.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AliasEntry {
+    pub alias: String,
+    #[serde(default)]
+    pub dev_type: Option<LogicDeviceType>,
+    #[serde(default)]
+    pub compatible: Option<String>,
+    #[serde(default)]
+    pub parent: Option<String>,
+}
+
+impl AliasEntry {
+    /// Whether a logical device satisfies this alias. `driver_name` is the name
+    /// it was registered with and `compatible` that of the node its driver is
+    /// bound to, if any.
+    pub fn matches(
+        &self,
+        dev_type: &LogicDeviceType,
+        driver_name: &str,
+        compatible: &[String],
+        parent_name: &str,
+    ) -> bool {
+        if self.dev_type.as_ref().is_some_and(|t| t != dev_type) {
+            return false;
+        }
+        if let Some(pattern) = &self.compatible {
+            if !glob_match(pattern, driver_name)
+                && !compatible.iter().any(|c| glob_match(pattern, c))
+            {
+                return false;
+            }
+        }
+        self.parent.as_ref().is_none_or(|pattern| glob_match(pattern, parent_name))
+    }
+}
+
+fn default_max_restarts() -> usize {
+    3
+}
+
+fn default_slot_quota() -> usize {
+    256
 }
 
 impl Manifest {
+    /// Parse drivers.json into its matching form.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut manifest =
+            serde_json::from_slice::<Self>(data).map_err(|_| Error::InvalidConfig)?;
+        manifest.normalize();
+        Ok(manifest)
+    }
+
+    /// Replace this manifest with `data`. One that fails to parse leaves the
+    /// current manifest untouched.
+    pub fn reload(&mut self, data: &[u8]) -> Result<(), Error> {
+        *self = Self::parse(data)?;
+        Ok(())
+    }
+
+    /// Derive the matching form of every driver's compatible strings. The
+    /// strings as written stay in `compatible` for display.
+    pub fn normalize(&mut self) {
+        for drv in self.drivers.iter_mut() {
+            drv.normalize();
+        }
+    }
+
     pub const fn new() -> Self {
-        Self { drivers: Vec::new() }
+        Self {
+            drivers: Vec::new(),
+            require_platform: false,
+            slot_quota: 256,
+            aliases: Vec::new(),
+            io_space: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_reload_keeps_the_old_manifest() {
+        let mut manifest = Manifest::parse(
+            br#"{"drivers":[{"name":"uart","binary":"uart","compatible":["ARM,PL011"]}]}"#,
+        )
+        .unwrap();
+
+        let broken = br#"{"drivers":[{"name":"net","binary":"#;
+        assert_eq!(manifest.reload(broken), Err(Error::InvalidConfig));
+        assert_eq!(manifest.drivers.len(), 1);
+        assert_eq!(manifest.drivers[0].name, "uart");
+        assert_eq!(manifest.drivers[0].compatible_norm, alloc::vec!["arm,pl011"]);
+
+        let next = br#"{"drivers":[{"name":"net","binary":"net","compatible":["virtio,net"]}]}"#;
+        assert_eq!(manifest.reload(next), Ok(()));
+        assert_eq!(manifest.drivers[0].name, "net");
+        assert_eq!(manifest.drivers[0].compatible_norm, alloc::vec!["virtio,net"]);
+    }
+
+    fn alias(compatible: Option<&str>, parent: Option<&str>) -> AliasEntry {
+        AliasEntry {
+            alias: "rootdisk".into(),
+            dev_type: Some(LogicDeviceType::Block),
+            compatible: compatible.map(String::from),
+            parent: parent.map(String::from),
+        }
+    }
+
+    #[test]
+    fn alias_resolves_by_compatible() {
+        let by_compat = alias(Some("virtio,blk*"), None);
+        let compatible = [String::from("virtio,blk-pci")];
+        assert!(by_compat.matches(&LogicDeviceType::Block, "virtio-blk", &compatible, "virtio0"));
+        // The driver's registered name counts as well, e.g. for a device without a node.
+        assert!(by_compat.matches(&LogicDeviceType::Block, "virtio,blk", &[], "virtio0"));
+        assert!(!by_compat.matches(&LogicDeviceType::Block, "ahci", &[], "sata0"));
+        assert!(!by_compat.matches(&LogicDeviceType::Net, "virtio-blk", &compatible, "virtio0"));
+    }
+
+    #[test]
+    fn alias_resolves_by_parent() {
+        let by_parent = alias(None, Some("virtio1"));
+        assert!(by_parent.matches(&LogicDeviceType::Block, "virtio-blk", &[], "virtio1"));
+        assert!(!by_parent.matches(&LogicDeviceType::Block, "virtio-blk", &[], "virtio0"));
+        let both = alias(Some("ahci*"), Some("sata*"));
+        assert!(both.matches(&LogicDeviceType::Block, "ahci-disk", &[], "sata0"));
+        assert!(!both.matches(&LogicDeviceType::Block, "ahci-disk", &[], "virtio0"));
     }
 }
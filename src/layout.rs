@@ -15,4 +15,5 @@ pub const RESOURCE_CAP: Page = Page::from(RESOURCE_SLOT);
 pub const MANIFEST_CAP: Page = Page::from(MANIFEST_SLOT);
 
 pub const RESOURCE_ADDR: usize = 0x3000_0000;
+pub const RESOURCE_WINDOW: usize = 0x0100_0000;
 pub const BOOTINFO_ADDR: usize = 0x3100_0000;
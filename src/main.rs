@@ -1,5 +1,5 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![allow(dead_code)]
 
 #[macro_use]
@@ -8,6 +8,7 @@ extern crate glenda;
 extern crate alloc;
 mod config;
 mod layout;
+mod proto;
 mod unicorn;
 
 use glenda::cap::CapType;
@@ -22,6 +23,7 @@ use glenda::utils::manager::{CSpaceManager, VSpaceManager};
 use layout::{INIT_CAP, INIT_SLOT};
 use unicorn::UnicornManager;
 
+#[cfg(not(test))]
 #[unsafe(no_mangle)]
 fn main() -> usize {
     glenda::console::init_logging("Unicorn");
@@ -0,0 +1,490 @@
+use crate::unicorn::BringupPhase;
+use crate::unicorn::platform::{DeviceSource, DeviceState};
+use alloc::string::String;
+use alloc::vec::Vec;
+use glenda::drivers::protocol::thermal::ThermalZones;
+use glenda::protocol::device::{LogicDeviceType, MMIORegion};
+use serde::{Deserialize, Serialize};
+
+// Unicorn specific labels on top of DEVICE_PROTO.
+// Kept well above the labels shared through `glenda::protocol::device`.
+pub const SUBSCRIBE: usize = 0x100;
+pub const UNSUBSCRIBE: usize = 0x101;
+pub const POLL_EVENTS: usize = 0x102;
+pub const GET_LOGIC_COUNTERS: usize = 0x103;
+pub const SEED_LOGIC_COUNTERS: usize = 0x104;
+pub const LIST_IRQS: usize = 0x105;
+pub const GET_RESOURCE_MAP: usize = 0x106;
+pub const BIND_UNBOUND: usize = 0x107;
+pub const GET_EVENT_LOG: usize = 0x108;
+pub const LIST_THERMAL_ZONES: usize = 0x109;
+pub const GET_SOURCE: usize = 0x10a;
+pub const GET_STATUS: usize = 0x10b;
+pub const DETACH_LOGIC: usize = 0x10c;
+pub const LIST_DEVICES_FOR_DRIVER: usize = 0x10d;
+pub const LIST_DEVICES: usize = 0x10e;
+// Manifest args of the calling driver. The process server's spawn takes only
+// the binary name, so drivers pull their args with this once started.
+pub const GET_DRIVER_ARGS: usize = 0x10f;
+// A driver is gone: sent by the driver itself or by whoever holds Unicorn's
+// unbadged endpoint. The process server does not send it yet, so a driver that
+// never leaves Starting is also treated as exited after a timeout.
+pub const DRIVER_EXITED: usize = 0x110;
+pub const REPORT_BATTERY: usize = 0x111;
+pub const GET_BATTERY_STATUS: usize = 0x112;
+pub const REPORT_GPIO: usize = 0x113;
+pub const GPIO_GET: usize = 0x114;
+pub const GPIO_SET: usize = 0x115;
+pub const TAKE_GPIO_WRITES: usize = 0x116;
+pub const DUMP_TREE: usize = 0x117;
+pub const REMOVE_LOGIC: usize = 0x118;
+pub const RELOAD_MANIFEST: usize = 0x119;
+pub const RESCAN_DEVICE: usize = 0x11a;
+pub const REPORT_NET_STATUS: usize = 0x11b;
+pub const GET_NET_STATUS: usize = 0x11c;
+pub const REPORT_FB_INFO: usize = 0x11d;
+pub const GET_FB_INFO: usize = 0x11e;
+pub const SET_PRIMARY_FB: usize = 0x11f;
+pub const SET_INPUT_FOCUS: usize = 0x120;
+pub const RELEASE_INPUT_FOCUS: usize = 0x121;
+pub const PUSH_INPUT: usize = 0x122;
+pub const POLL_INPUT: usize = 0x123;
+pub const UART_CONFIGURE: usize = 0x124;
+pub const UART_WRITE: usize = 0x125;
+pub const UART_READ: usize = 0x126;
+pub const UART_TAKE_TX: usize = 0x127;
+pub const UART_PUSH_RX: usize = 0x128;
+pub const ARM_ONESHOT: usize = 0x129;
+pub const ARM_PERIODIC: usize = 0x12a;
+pub const CANCEL_TIMER: usize = 0x12b;
+pub const TAKE_TIMER_COMMANDS: usize = 0x12c;
+pub const TIMER_FIRED: usize = 0x12d;
+// 0x12e was SET_IRQ_AFFINITY, dropped: the IRQ controller cap cannot route a line.
+pub const QUERY_DETAILED: usize = 0x12f;
+pub const GET_ENUMERATION_MAP: usize = 0x130;
+pub const REPORT_THERMAL_ZONES: usize = 0x131;
+pub const REPORT_BLOCK_RANGE: usize = 0x132;
+pub const GET_LOGICAL_DESC: usize = 0x133;
+// Hardware identity (PCI BDF plus serial, GPT disk GUID ...) of the logical
+// device the calling driver registers next; its name is pinned to it.
+pub const STAGE_LOGIC_IDENTITY: usize = 0x134;
+// A driver serviced IRQ `mr0` of its device; the line is acked once every
+// driver sharing it did.
+pub const IRQ_DONE: usize = 0x135;
+
+// Badge sent to subscribers when new events are queued for them.
+pub const NOTIFY_EVENT: usize = 0x2;
+// Badge sent to a gpio driver when clients queued line writes for it.
+pub const NOTIFY_GPIO: usize = 0x4;
+// Badge sent to the focused input consumer when events are queued for it.
+pub const NOTIFY_INPUT: usize = 0x8;
+// Badge sent to a uart driver when tx bytes or a new line config are queued.
+pub const NOTIFY_UART: usize = 0x10;
+// Badge sent to a timer driver when arm/cancel requests are queued for it.
+pub const NOTIFY_TIMER: usize = 0x20;
+// Badge sent on a logical device's endpoint once Unicorn dropped the device;
+// the driver must stop serving every client badge it holds for it.
+pub const NOTIFY_REVOKED: usize = 0x40;
+// Badge sent on every endpoint a driver registered when Unicorn shuts down; the
+// driver acknowledges by reporting `Stopped`/`Exited` or by exiting.
+pub const NOTIFY_STOP: usize = 0x80;
+// Badge bit of IRQ notifications on the endpoint a driver passed to GET_IRQ.
+// IRQ `id` of its device additionally sets bit `IRQ_BADGE_SHIFT + id`, so
+// pending lines OR together without reading as any other NOTIFY_* bit.
+pub const NOTIFY_IRQ: usize = 0x100;
+pub const IRQ_BADGE_SHIFT: usize = 9;
+
+// Subscription mask bits
+pub const EVENT_STATE: usize = 1 << 0;
+pub const EVENT_DETACH: usize = 1 << 1;
+pub const EVENT_HOTPLUG: usize = 1 << 2;
+pub const EVENT_DRIVER: usize = 1 << 3;
+pub const EVENT_ENUMERATION: usize = 1 << 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DeviceEvent {
+    StateChanged { name: String, old: DeviceState, new: DeviceState, reason: Option<String> },
+    Detaching { name: String },
+    DevicesAdded { names: Vec<String> },
+    LogicAdded { name: String, dev_type: LogicDeviceType },
+    LogicRemoved { name: String },
+    DriverStarted { device: String, pid: usize, binary: String },
+    DriverExited { device: String, pid: usize },
+    // A logical device got a name enumeration.json does not hold yet. Unicorn
+    // cannot write config files; the subscriber saves GET_ENUMERATION_MAP.
+    EnumerationChanged,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogKind {
+    DeviceAdded,
+    DriverSpawned,
+    DriverFailed,
+    StateChanged,
+}
+
+/// Entry of the in-memory event log. `seq` is a monotonically increasing
+/// stamp; Unicorn has no wall clock of its own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+    pub seq: u64,
+    pub kind: LogKind,
+    pub subject: String,
+    pub detail: String,
+}
+
+/// One node of a DUMP_TREE snapshot. Entries come in BFS order from the root,
+/// so a parent always precedes its children.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TreeNodeEntry {
+    pub id: u32,
+    pub parent: Option<u32>,
+    pub name: String,
+    pub compatible: Vec<String>,
+    pub source: DeviceSource,
+    pub state: DeviceState,
+    pub mmio: Vec<MMIORegion>,
+    pub irq: Vec<usize>,
+}
+
+/// One QUERY_DETAILED match. Tree nodes carry a state and their parent node;
+/// logical devices carry their type and the parent they were registered under.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceSummary {
+    pub name: String,
+    pub dev_type: Option<LogicDeviceType>,
+    pub state: Option<DeviceState>,
+    pub parent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceListEntry {
+    pub id: u32,
+    pub name: String,
+    pub compatible: Vec<String>,
+    pub mmio_count: usize,
+    pub irq_count: usize,
+    pub state: DeviceState,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BoundDevice {
+    pub name: String,
+    pub state: DeviceState,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnicornStatus {
+    pub phase: BringupPhase,
+    pub configured_drivers: usize,
+    pub spawned_drivers: usize,
+    pub running_drivers: usize,
+    pub blocked_nodes: usize,
+    pub devices: usize,
+    pub logic_devices: usize,
+}
+
+/// Zones reported by one thermal driver, together with the device it owns.
+#[derive(Serialize, Debug)]
+pub struct ThermalZoneInfo<'a> {
+    pub driver: usize,
+    pub owner: &'a str,
+    pub zones: &'a ThermalZones,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryState {
+    Unknown,
+    Charging,
+    Discharging,
+    NotCharging,
+    Full,
+}
+
+/// Last reading pushed by a battery driver. Negative `current_ma` means discharging.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct BatteryStatus {
+    pub percent: u8,
+    pub voltage_mv: u32,
+    pub current_ma: i32,
+    pub state: BatteryState,
+}
+
+impl BatteryStatus {
+    pub fn is_valid(&self) -> bool {
+        self.percent <= 100
+    }
+}
+
+/// Blocks a disk or partition spans on its parent, pushed by its driver.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRange {
+    pub start_lba: u64,
+    pub num_blocks: u64,
+}
+
+/// Reply of GET_LOGICAL_DESC. `start_lba`/`num_blocks` are only set for block
+/// devices and partitions whose driver reported their range.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogicalDesc {
+    pub name: String,
+    pub dev_type: LogicDeviceType,
+    pub parent_name: String,
+    pub driver_name: String,
+    pub start_lba: Option<u64>,
+    pub num_blocks: Option<u64>,
+}
+
+impl LogicalDesc {
+    pub fn new(
+        name: String,
+        dev_type: LogicDeviceType,
+        parent_name: String,
+        driver_name: String,
+        range: Option<&BlockRange>,
+    ) -> Self {
+        Self {
+            name,
+            dev_type,
+            parent_name,
+            driver_name,
+            start_lba: range.map(|r| r.start_lba),
+            num_blocks: range.map(|r| r.num_blocks),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetStatus {
+    pub mac: [u8; 6],
+    pub link_up: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Unknown,
+    Rgb565,
+    Rgb888,
+    Xrgb8888,
+    Argb8888,
+}
+
+/// Current mode of a framebuffer. `stride` is in pixels.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FbInfo {
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub format: PixelFormat,
+}
+
+impl FbInfo {
+    pub fn is_valid(&self) -> bool {
+        self.width != 0 && self.height != 0 && self.stride >= self.width
+    }
+}
+
+/// Raw input event (evdev style type/code/value). `device` is filled in by
+/// Unicorn with the logical name of the input device that pushed it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InputEvent {
+    pub device: String,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartParity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartConfig {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: UartParity,
+    pub stop_bits: u8,
+}
+
+/// Request queued for a timer driver. `deadline_ns` is absolute in the
+/// driver's clock; periodic timers ignore it and fire every `period_ns`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerCommand {
+    Arm { id: usize, deadline_ns: u64, period_ns: Option<u64> },
+    Cancel { id: usize },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IrqInfo {
+    pub irq: usize,
+    pub devices: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MmioResource {
+    pub base_addr: usize,
+    pub size: usize,
+    pub granted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IrqResource {
+    pub irq: usize,
+    pub registered: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResourceMap {
+    pub name: String,
+    pub mmio: Vec<MmioResource>,
+    pub irq: Vec<IrqResource>,
+}
+
+impl DeviceEvent {
+    pub fn kind(&self) -> usize {
+        match self {
+            DeviceEvent::StateChanged { .. } => EVENT_STATE,
+            DeviceEvent::Detaching { .. } => EVENT_DETACH,
+            DeviceEvent::DevicesAdded { .. }
+            | DeviceEvent::LogicAdded { .. }
+            | DeviceEvent::LogicRemoved { .. } => EVENT_HOTPLUG,
+            DeviceEvent::DriverStarted { .. } | DeviceEvent::DriverExited { .. } => EVENT_DRIVER,
+            DeviceEvent::EnumerationChanged => EVENT_ENUMERATION,
+        }
+    }
+}
+
+/// REGISTER_LOGIC contract: every logical device is reached through the endpoint
+/// sent along with the descriptor (`MsgFlags::HAS_CAP`). Only `Platform` is a pure
+/// metadata entry and may be registered without one. A missing or null endpoint
+/// for any other type is rejected with `Error::InvalidArgs`; allocating a
+/// `Platform` entry fails with `Error::InvalidType`.
+pub fn logic_requires_endpoint(dev_type: LogicDeviceType) -> bool {
+    !matches!(dev_type, LogicDeviceType::Platform)
+}
+
+/// Notification badge for IRQ `id` of a driver's device, `None` if `id` has no bit.
+pub fn irq_badge(id: usize) -> Option<usize> {
+    let bit = IRQ_BADGE_SHIFT.checked_add(id).filter(|bit| *bit < usize::BITS as usize)?;
+    Some(NOTIFY_IRQ | (1 << bit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IRQ ids a driver decodes from a notification badge.
+    fn irq_ids(badge: usize) -> impl Iterator<Item = usize> {
+        let ids = if badge & NOTIFY_IRQ != 0 { badge >> IRQ_BADGE_SHIFT } else { 0 };
+        (0..usize::BITS as usize - IRQ_BADGE_SHIFT).filter(move |id| ids & (1 << id) != 0)
+    }
+
+    #[test]
+    fn irq_badges_never_read_as_other_notifications() {
+        for id in [0, 1, 6, 7] {
+            let badge = irq_badge(id).unwrap();
+            assert_eq!(badge & (NOTIFY_REVOKED | NOTIFY_STOP), 0);
+            assert_eq!(irq_ids(badge).collect::<Vec<_>>(), alloc::vec![id]);
+        }
+        assert_eq!(irq_badge(usize::BITS as usize - IRQ_BADGE_SHIFT), None);
+    }
+
+    #[test]
+    fn pending_irqs_or_together() {
+        let badge = irq_badge(0).unwrap() | irq_badge(3).unwrap() | NOTIFY_STOP;
+        assert_eq!(irq_ids(badge).collect::<Vec<_>>(), alloc::vec![0, 3]);
+        assert_ne!(badge & NOTIFY_STOP, 0);
+        assert_eq!(irq_ids(NOTIFY_STOP | NOTIFY_REVOKED).count(), 0);
+    }
+
+    #[test]
+    fn only_platform_entries_go_without_endpoint() {
+        assert!(!logic_requires_endpoint(LogicDeviceType::Platform));
+        for dev_type in [
+            LogicDeviceType::Block,
+            LogicDeviceType::Volume,
+            LogicDeviceType::Net,
+            LogicDeviceType::Fb,
+            LogicDeviceType::Uart,
+            LogicDeviceType::Input,
+            LogicDeviceType::Timer,
+            LogicDeviceType::Gpio,
+            LogicDeviceType::Battery,
+        ] {
+            assert!(logic_requires_endpoint(dev_type), "{:?}", dev_type);
+        }
+    }
+
+    #[test]
+    fn battery_reading_reaches_the_client_unchanged() {
+        let reading = BatteryStatus {
+            percent: 87,
+            voltage_mv: 4120,
+            current_ma: -350,
+            state: BatteryState::Discharging,
+        };
+        assert!(reading.is_valid());
+        assert!(!BatteryStatus { percent: 101, ..reading }.is_valid());
+
+        let bytes = postcard::to_allocvec(&reading).unwrap();
+        let decoded: BatteryStatus = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.percent, 87);
+        assert_eq!(decoded.voltage_mv, 4120);
+        assert_eq!(decoded.current_ma, -350);
+        assert_eq!(decoded.state, BatteryState::Discharging);
+    }
+
+    #[test]
+    fn net_report_carries_mac_and_link() {
+        let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let report = (String::from("net0"), NetStatus { mac, link_up: true });
+        let bytes = postcard::to_allocvec(&report).unwrap();
+        let (name, status): (String, NetStatus) = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(name, "net0");
+        assert_eq!(status.mac, mac);
+        assert!(status.link_up);
+    }
+
+    #[test]
+    fn fb_mode_needs_a_stride_covering_the_width() {
+        let mode = FbInfo { width: 1024, height: 768, stride: 1024, format: PixelFormat::Xrgb8888 };
+        assert!(mode.is_valid());
+        assert!(FbInfo { stride: 1280, ..mode }.is_valid());
+        assert!(!FbInfo { stride: 800, ..mode }.is_valid());
+        assert!(!FbInfo { height: 0, ..mode }.is_valid());
+    }
+
+    #[test]
+    fn disk_and_partition_descriptors() {
+        let disk = LogicalDesc::new(
+            "disk0".into(),
+            LogicDeviceType::Block,
+            "virtio0".into(),
+            "virtio-blk".into(),
+            None,
+        );
+        let range = BlockRange { start_lba: 2048, num_blocks: 1 << 20 };
+        let part = LogicalDesc::new(
+            "disk0p1".into(),
+            LogicDeviceType::Volume,
+            "disk0".into(),
+            "gpt".into(),
+            Some(&range),
+        );
+
+        let bytes = postcard::to_allocvec(&alloc::vec![disk, part]).unwrap();
+        let decoded: Vec<LogicalDesc> = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded[0].name, "disk0");
+        assert_eq!(decoded[0].dev_type, LogicDeviceType::Block);
+        assert_eq!(decoded[0].parent_name, "virtio0");
+        assert_eq!((decoded[0].start_lba, decoded[0].num_blocks), (None, None));
+        assert_eq!(decoded[1].name, "disk0p1");
+        assert_eq!(decoded[1].dev_type, LogicDeviceType::Volume);
+        assert_eq!(decoded[1].parent_name, "disk0");
+        assert_eq!(decoded[1].start_lba, Some(2048));
+        assert_eq!(decoded[1].num_blocks, Some(1 << 20));
+    }
+}
@@ -1,19 +1,153 @@
-use super::platform::{DeviceId, DeviceState};
+use super::logic::{LogicDeviceCounter, LogicDeviceHandle};
+use super::platform::{DeviceId, DeviceSource, DeviceState};
+use super::work::WorkTask;
+use crate::config::IoWindow;
 use crate::layout::{IRQ_CONTROL_CAP, KERNEL_CAP};
-use crate::unicorn::UnicornManager;
-use alloc::collections::VecDeque;
+use crate::proto::{
+    BatteryStatus, BlockRange, DeviceEvent, DeviceListEntry, DeviceSummary, FbInfo, IrqInfo,
+    IrqResource, LogKind, LogicalDesc, MmioResource, NetStatus, ResourceMap, ThermalZoneInfo,
+    TreeNodeEntry, irq_badge,
+};
+use crate::unicorn::matcher::{glob_match, is_glob, normalize_compatible};
+use crate::unicorn::{UnicornManager, is_null_cap};
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use glenda::arch::mem::PGSIZE;
 use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint, IrqHandler, Page, Rights};
+use glenda::drivers::protocol::thermal::ThermalZones;
 use glenda::error::Error;
 use glenda::interface::CSpaceService;
 use glenda::interface::DeviceService;
-use glenda::interface::VSpaceService;
 use glenda::ipc::Badge;
 use glenda::mem::Perms;
-use glenda::protocol::device::{self, DeviceDescNode, HookTarget, LogicDeviceDesc, NOTIFY_HOOK};
+use glenda::protocol::device::{
+    self, DeviceDescNode, HookTarget, LogicDeviceDesc, LogicDeviceType, NOTIFY_HOOK,
+};
 use glenda::protocol::init::ServiceState;
 
+// Work queue ticks (1s) a detached logical device stays alive for its holders to let go.
+const DETACH_GRACE_TICKS: usize = 100;
+
+/// Whether `[base, base + size)` lies inside one IO-space window. No windows
+/// configured means the IO space is unknown and every region passes.
+fn in_io_space(windows: &[IoWindow], base: usize, size: usize) -> bool {
+    let Some(end) = base.checked_add(size) else {
+        return false;
+    };
+    windows.is_empty()
+        || windows
+            .iter()
+            .any(|w| w.base <= base && w.base.checked_add(w.size).is_some_and(|e| end <= e))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportedExit {
+    // Stopped on request (NOTIFY_STOP during shutdown) or left on purpose.
+    Clean,
+    Crashed,
+}
+
+/// Whether a state a driver reports about itself means it is gone. Its pid
+/// and resources are released either way; only a crash is restarted.
+fn reported_exit(status: ServiceState) -> Option<ReportedExit> {
+    match status {
+        ServiceState::Stopped | ServiceState::Exited => Some(ReportedExit::Clean),
+        ServiceState::Failed => Some(ReportedExit::Crashed),
+        ServiceState::Starting | ServiceState::Running => None,
+    }
+}
+
+/// New link state of a net device whose report flipped it. The first report
+/// is not a change.
+fn link_change(old: Option<NetStatus>, new: NetStatus) -> Option<bool> {
+    old.filter(|old| old.link_up != new.link_up).map(|_| new.link_up)
+}
+
+/// The framebuffer picked with SET_PRIMARY_FB, or the first one registered
+/// while none is picked or the picked one is gone. `fbs` is (id, name) in
+/// registration order.
+fn primary_fb<'n>(picked: Option<usize>, fbs: &[(usize, &'n str)]) -> Option<&'n str> {
+    fbs.iter().find(|(id, _)| Some(*id) == picked).or(fbs.first()).map(|(_, name)| *name)
+}
+
+/// Whether a hook on `target` fires for the logical device behind `ep`.
+fn hook_fires(target: &HookTarget, ep: CapPtr, dev_type: &LogicDeviceType) -> bool {
+    match target {
+        HookTarget::Endpoint(e) => *e == ep.bits() as usize,
+        HookTarget::Type(t) => t == dev_type,
+    }
+}
+
+fn same_target(a: &HookTarget, b: &HookTarget) -> bool {
+    match (a, b) {
+        (HookTarget::Endpoint(a), HookTarget::Endpoint(b)) => a == b,
+        (HookTarget::Type(a), HookTarget::Type(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn mmio_pages(size: usize) -> usize {
+    (size + PGSIZE - 1) / PGSIZE
+}
+
+/// Base of the recorded grant whose pages fully cover `[base, base + size)`.
+fn covering_grant(
+    grants: &BTreeMap<usize, (usize, usize)>,
+    base: usize,
+    size: usize,
+) -> Option<usize> {
+    let end = base.checked_add(size)?;
+    grants
+        .range(..=base)
+        .rev()
+        .find(|&(&grant_base, &(grant_size, _))| {
+            end <= grant_base + mmio_pages(grant_size) * PGSIZE
+        })
+        .map(|(&grant_base, _)| grant_base)
+}
+
+/// Cspace slots handed out per client badge, for the manifest's `slot_quota`.
+pub struct SlotUsage {
+    used: BTreeMap<usize, usize>,
+}
+
+impl SlotUsage {
+    pub fn new() -> Self {
+        Self { used: BTreeMap::new() }
+    }
+
+    pub fn used(&self, badge: usize) -> usize {
+        self.used.get(&badge).copied().unwrap_or(0)
+    }
+
+    pub fn charge(&mut self, badge: usize, count: usize, quota: usize) -> Result<(), Error> {
+        let used = self.used(badge);
+        if used.saturating_add(count) > quota {
+            return Err(Error::ResourceLimit);
+        }
+        if count > 0 {
+            self.used.insert(badge, used + count);
+        }
+        Ok(())
+    }
+
+    pub fn refund(&mut self, badge: usize, count: usize) {
+        let Some(used) = self.used.get_mut(&badge) else {
+            return;
+        };
+        *used = used.saturating_sub(count);
+        if *used == 0 {
+            self.used.remove(&badge);
+        }
+    }
+
+    /// Drop whatever a client that went away was charged.
+    pub fn forget(&mut self, badge: usize) {
+        self.used.remove(&badge);
+    }
+}
+
 impl<'a> UnicornManager<'a> {
     pub(super) fn report_frame_cap(
         &mut self,
@@ -25,24 +159,14 @@ impl<'a> UnicornManager<'a> {
             return Err(Error::InvalidArgs);
         }
 
-        let pages = (byte_len + PGSIZE - 1) / PGSIZE;
-        self.vspace_mgr.map_page(
-            Page::from(frame_slot),
-            crate::layout::RESOURCE_ADDR,
-            Perms::READ,
-            pages,
-            self.res_client,
-            self.cspace_mgr,
-        )?;
+        let (vaddr, pages) = self.map_transient(Page::from(frame_slot), byte_len, Perms::READ)?;
 
         let parse_res = {
-            let data = unsafe {
-                core::slice::from_raw_parts(crate::layout::RESOURCE_ADDR as *const u8, byte_len)
-            };
+            let data = unsafe { core::slice::from_raw_parts(vaddr as *const u8, byte_len) };
             postcard::from_bytes::<Vec<DeviceDescNode>>(data).map_err(|_| Error::InvalidType)
         };
 
-        let _ = self.vspace_mgr.unmap(crate::layout::RESOURCE_ADDR, pages);
+        let _ = self.unmap_transient(vaddr, pages);
         let _ = CSPACE_CAP.delete(frame_slot);
 
         let desc = parse_res?;
@@ -50,23 +174,12 @@ impl<'a> UnicornManager<'a> {
     }
 
     fn scan_subtree(&mut self, start_id: DeviceId) -> Result<(), Error> {
-        let mut queue = VecDeque::new();
-        queue.push_back(start_id);
-
-        while let Some(id) = queue.pop_front() {
-            let (can_start, children) = if let Some(node) = self.tree.get_node(id) {
-                (self.can_start_node(id), node.children.clone())
-            } else {
-                (false, alloc::vec![])
-            };
-
-            if can_start {
+        // Only Ready nodes are started, so initializing ones are never handed
+        // to a second driver.
+        for id in self.tree.walk(start_id)? {
+            if self.can_start_node(id) {
                 self.enqueue_if_absent(id);
             }
-
-            for child in children {
-                queue.push_back(child);
-            }
         }
         Ok(())
     }
@@ -74,18 +187,14 @@ impl<'a> UnicornManager<'a> {
     fn notify_hook_on_logic(
         &self,
         logic_id: usize,
-        hooks: &[(HookTarget, CapPtr)],
+        hooks: &[(usize, HookTarget, CapPtr)],
     ) -> Result<(), Error> {
         let (desc, ep, name) =
             self.logic_service.devices.get(&logic_id).cloned().ok_or(Error::NotFound)?;
 
         let mut notify_eps = Vec::new();
-        for (target, hook_ep) in hooks {
-            let notify = match target {
-                HookTarget::Endpoint(e) => *e == ep.bits() as usize,
-                HookTarget::Type(t) => *t == desc.dev_type,
-            };
-            if notify {
+        for (_, target, hook_ep) in hooks {
+            if hook_fires(target, ep, &desc.dev_type) {
                 notify_eps.push(*hook_ep);
             }
         }
@@ -97,22 +206,486 @@ impl<'a> UnicornManager<'a> {
         Ok(())
     }
 
-    fn find_node_by_name(&self, name: &str) -> Option<DeviceId> {
-        if let Some(root) = self.tree.root {
-            let mut queue = VecDeque::new();
-            queue.push_back(root);
-            while let Some(id) = queue.pop_front() {
-                if let Some(node) = self.tree.get_node(id) {
-                    if node.desc.name == name {
-                        return Some(id);
-                    }
-                    for child in &node.children {
-                        queue.push_back(*child);
-                    }
+    pub fn get_logic_counters(&self, _badge: Badge) -> LogicDeviceCounter {
+        self.logic_service.counter.clone()
+    }
+
+    /// Restore naming counters persisted by an earlier run. Only the unbadged
+    /// endpoint (init) may seed them; a driver could otherwise shift names.
+    pub fn seed_logic_counters(
+        &mut self,
+        badge: Badge,
+        seed: LogicDeviceCounter,
+    ) -> Result<(), Error> {
+        if badge.bits() != Badge::null().bits() {
+            warn!("Client {} may not seed the logical device counters", badge);
+            return Err(Error::InvalidArgs);
+        }
+        self.logic_service.seed_counter(seed)
+    }
+
+    pub fn list_irqs(&self, _badge: Badge) -> Vec<IrqInfo> {
+        self.irq_caps
+            .keys()
+            .map(|&irq| IrqInfo {
+                irq,
+                devices: self
+                    .irqs
+                    .get(&irq)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(id, _)| self.tree.get_node(*id))
+                    .map(|node| node.desc.name.clone())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Read-only view of a node's resources; never mints or grants caps.
+    pub fn get_resource_map(&self, _badge: Badge, name: &str) -> Result<ResourceMap, Error> {
+        let id = self.find_node_by_name(name).ok_or(Error::NotFound)?;
+        let node = self.tree.get_node(id).ok_or(Error::NotFound)?;
+        Ok(ResourceMap {
+            name: node.desc.name.clone(),
+            mmio: node
+                .desc
+                .mmio
+                .iter()
+                .map(|reg| MmioResource {
+                    base_addr: reg.base_addr,
+                    size: reg.size,
+                    granted: self.mmio_caps.contains_key(&reg.base_addr),
+                })
+                .collect(),
+            irq: node
+                .desc
+                .irq
+                .iter()
+                .map(|&irq| IrqResource { irq, registered: self.irq_caps.contains_key(&irq) })
+                .collect(),
+        })
+    }
+
+    pub fn list_devices(&self, _badge: Badge) -> Vec<DeviceListEntry> {
+        self.tree.list_entries()
+    }
+
+    /// `query` with type, state and parent of every match, saving clients a
+    /// GET_DESC round-trip per name.
+    pub fn query_detailed(
+        &mut self,
+        badge: Badge,
+        query: device::DeviceQuery,
+    ) -> Result<Vec<DeviceSummary>, Error> {
+        let names = self.query(badge, query)?;
+        Ok(names.into_iter().map(|name| self.summarize(name)).collect())
+    }
+
+    fn summarize(&self, name: String) -> DeviceSummary {
+        if let Some(summary) = self.tree.summarize(&name) {
+            return summary;
+        }
+        let desc = self.logic_service.get_desc(&name).map(|(_, d)| d);
+        DeviceSummary {
+            name,
+            dev_type: desc.as_ref().map(|d| d.dev_type),
+            state: None,
+            parent: desc.map(|d| d.parent_name),
+        }
+    }
+
+    pub fn dump_tree(&self, _badge: Badge) -> Vec<TreeNodeEntry> {
+        self.tree.dump_entries()
+    }
+
+    /// Re-run driver matching below `name` only, e.g. after a hotplug on one bus.
+    pub fn rescan_device(&mut self, _badge: Badge, name: &str) -> Result<(), Error> {
+        let id = self.find_node_by_name(name).ok_or(Error::NotFound)?;
+        self.scan_subtree(id)
+    }
+
+    pub fn get_source(&self, _badge: Badge, name: &str) -> Result<DeviceSource, Error> {
+        let id = self.find_node_by_name(name).ok_or(Error::NotFound)?;
+        self.tree.get_node(id).map(|node| node.source).ok_or(Error::NotFound)
+    }
+
+    /// Tell every holder of `name` that it is going away and revoke the device
+    /// once the grace period ran out; the heartbeat timer wakes us for it even
+    /// when nothing else is happening. Returns the holders notified. Only the
+    /// owning driver or the unbadged endpoint may detach a device.
+    pub fn detach_logic(&mut self, badge: Badge, name: &str) -> Result<usize, Error> {
+        let (id, holders) = self.logic_service.detach(name, badge)?;
+        let notified = self
+            .event_service
+            .publish_to(&holders, DeviceEvent::Detaching { name: name.to_string() });
+        log!("Detaching {}: {} holders, {} notified", name, holders.len(), notified);
+        if self.defer(WorkTask::RevokeLogic(id), Some(DETACH_GRACE_TICKS)).is_err() {
+            self.revoke_logic(id);
+        }
+        Ok(notified)
+    }
+
+    /// Hot-unplug path for the driver that registered `id`: unlike `detach_logic`
+    /// there is no grace period, the device is revoked right away.
+    pub fn remove_logic(&mut self, badge: Badge, handle: usize) -> Result<(), Error> {
+        let id = self.logic_service.resolve(LogicDeviceHandle::from_bits(handle))?;
+        match self.logic_service.owners.get(&id) {
+            Some(&owner) if owner == badge.bits() => {}
+            Some(_) => return Err(Error::InvalidArgs),
+            None => return Err(Error::NotFound),
+        }
+        self.revoke_logic(id);
+        Ok(())
+    }
+
+    /// Drop a logical device and tell its driver to stop serving its holders.
+    pub fn revoke_logic(&mut self, id: usize) {
+        if let Some((desc, name)) = self.logic_service.revoke(id) {
+            if let Some(node_id) = self.find_node_by_name(&desc.parent_name) {
+                if let Some(node) = self.tree.get_node_mut(node_id) {
+                    node.logical_devices.retain(|l| *l != id);
+                }
+            }
+            self.battery_status.remove(&id);
+            self.net_status.remove(&id);
+            self.fb_info.remove(&id);
+            self.block_ranges.remove(&id);
+            self.uart_service.ports.remove(&id);
+            self.timer_service.drop_device(id);
+            if self.primary_fb == Some(id) {
+                self.primary_fb = None;
+            }
+            self.gpio_service.banks.remove(&id);
+            log!("Logical device {} detached", name);
+            self.event_service.publish(DeviceEvent::LogicRemoved { name });
+        }
+    }
+
+    /// Id of logical device `name` of type `dev_type`, provided `badge` is the
+    /// driver that registered it.
+    fn owned_logic_id(
+        &self,
+        badge: Badge,
+        name: &str,
+        dev_type: LogicDeviceType,
+    ) -> Result<usize, Error> {
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != dev_type {
+            return Err(Error::InvalidType);
+        }
+        if self.logic_service.owners.get(&id) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        Ok(id)
+    }
+
+    /// Battery drivers push their readings; only the driver that registered
+    /// `name` may update it.
+    pub fn report_battery(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        status: BatteryStatus,
+    ) -> Result<(), Error> {
+        let id = self.owned_logic_id(badge, name, LogicDeviceType::Battery)?;
+        if !status.is_valid() {
+            return Err(Error::InvalidArgs);
+        }
+        self.battery_status.insert(id, status);
+        Ok(())
+    }
+
+    /// Cached status of battery `name`, or of the first battery when `name` is empty.
+    pub fn get_battery_status(&self, _badge: Badge, name: &str) -> Result<BatteryStatus, Error> {
+        let id = if name.is_empty() {
+            *self.battery_status.keys().next().ok_or(Error::NotFound)?
+        } else {
+            self.logic_service.get_desc(name).ok_or(Error::NotFound)?.0
+        };
+        self.battery_status.get(&id).copied().ok_or(Error::NotFound)
+    }
+
+    pub fn report_net_status(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        status: NetStatus,
+    ) -> Result<(), Error> {
+        let id = self.owned_logic_id(badge, name, LogicDeviceType::Net)?;
+        let old = self.net_status.insert(id, status);
+        if let Some(up) = link_change(old, status) {
+            log!("{}: link {}", name, if up { "up" } else { "down" });
+        }
+        Ok(())
+    }
+
+    /// MAC address and link state of `name` as last reported by its driver.
+    pub fn get_net_status(&self, _badge: Badge, name: &str) -> Result<NetStatus, Error> {
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Net {
+            return Err(Error::InvalidType);
+        }
+        self.net_status.get(&id).copied().ok_or(Error::NotFound)
+    }
+
+    /// Disk and partition drivers push the block range their device covers.
+    pub fn report_block_range(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        range: BlockRange,
+    ) -> Result<(), Error> {
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if !matches!(desc.dev_type, LogicDeviceType::Block | LogicDeviceType::Volume) {
+            return Err(Error::InvalidType);
+        }
+        if self.logic_service.owners.get(&id) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        if range.num_blocks == 0 || range.start_lba.checked_add(range.num_blocks).is_none() {
+            return Err(Error::InvalidArgs);
+        }
+        self.block_ranges.insert(id, range);
+        Ok(())
+    }
+
+    /// Remember the identity the calling driver's next REGISTER_LOGIC is named by.
+    pub fn stage_logic_identity(&mut self, badge: Badge, identity: String) -> Result<(), Error> {
+        if !self.pids.contains_key(&badge.bits()) || identity.is_empty() {
+            return Err(Error::InvalidArgs);
+        }
+        self.staged_identities.insert(badge.bits(), identity);
+        Ok(())
+    }
+
+    /// Descriptor of logical device `name` (disk0, net1, disk0p1 ...).
+    pub fn get_logical_desc(&self, _badge: Badge, name: &str) -> Result<LogicalDesc, Error> {
+        let aliased = self.resolve_alias(name);
+        let name = aliased.as_deref().unwrap_or(name);
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        Ok(LogicalDesc::new(
+            name.to_string(),
+            desc.dev_type,
+            desc.parent_name,
+            desc.name,
+            self.block_ranges.get(&id),
+        ))
+    }
+
+    pub fn report_fb_info(&mut self, badge: Badge, name: &str, info: FbInfo) -> Result<(), Error> {
+        let id = self.owned_logic_id(badge, name, LogicDeviceType::Fb)?;
+        if !info.is_valid() {
+            return Err(Error::InvalidArgs);
+        }
+        self.fb_info.insert(id, info);
+        Ok(())
+    }
+
+    /// Mode of framebuffer `name`; an empty name means the primary one.
+    pub fn get_fb_info(&self, _badge: Badge, name: &str) -> Result<FbInfo, Error> {
+        let name = if name.is_empty() {
+            self.primary_fb_name().ok_or(Error::NotFound)?
+        } else {
+            name.to_string()
+        };
+        let (id, desc) = self.logic_service.get_desc(&name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Fb {
+            return Err(Error::InvalidType);
+        }
+        self.fb_info.get(&id).copied().ok_or(Error::NotFound)
+    }
+
+    pub fn set_primary_fb(&mut self, _badge: Badge, name: &str) -> Result<(), Error> {
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Fb {
+            return Err(Error::InvalidType);
+        }
+        log!("Primary framebuffer: {}", name);
+        self.primary_fb = Some(id);
+        Ok(())
+    }
+
+    /// Name of the logical device a manifest alias points at this boot, if `name`
+    /// is an alias and something currently matches it.
+    fn resolve_alias(&self, name: &str) -> Option<String> {
+        let alias = self.config.aliases.iter().find(|a| a.alias == name)?;
+        let resolved = self.logic_service.devices.iter().find(|(id, (desc, _, _))| {
+            if self.logic_service.detached.contains(id) {
+                return false;
+            }
+            let node = self
+                .logic_service
+                .owners
+                .get(id)
+                .and_then(|driver| self.pids.get(driver))
+                .and_then(|node| self.tree.get_node(*node));
+            let compatible = node.map(|n| n.desc.compatible.as_slice()).unwrap_or_default();
+            alias.matches(&desc.dev_type, &desc.name, compatible, &desc.parent_name)
+        });
+        resolved.map(|(_, (_, _, assigned))| assigned.clone())
+    }
+
+    /// The selected primary framebuffer, falling back to the first registered one.
+    fn primary_fb_name(&self) -> Option<String> {
+        let fbs: Vec<_> = self
+            .logic_service
+            .devices
+            .iter()
+            .filter(|(id, (desc, _, _))| {
+                desc.dev_type == LogicDeviceType::Fb && !self.logic_service.detached.contains(id)
+            })
+            .map(|(id, (_, _, name))| (*id, name.as_str()))
+            .collect();
+        primary_fb(self.primary_fb, &fbs).map(ToString::to_string)
+    }
+
+    /// Called by a thermal driver with its current zones; replaces what it
+    /// reported before. The zones are listed under the driver's device.
+    pub fn report_thermal_zones(&mut self, badge: Badge, zones: ThermalZones) -> Result<(), Error> {
+        let &node_id = self.pids.get(&badge.bits()).ok_or(Error::InvalidArgs)?;
+        let owner = self.tree.get_node(node_id).ok_or(Error::NotFound)?.desc.name.clone();
+        self.thermal_zones.insert(badge.bits(), (zones, owner));
+        Ok(())
+    }
+
+    pub fn list_thermal_zones(&self, _badge: Badge) -> Vec<ThermalZoneInfo<'_>> {
+        self.thermal_zones
+            .iter()
+            .map(|(&driver, (zones, owner))| ThermalZoneInfo { driver, owner, zones })
+            .collect()
+    }
+
+    fn driver_alive(&self, driver_id: usize) -> bool {
+        self.pids.contains_key(&driver_id)
+            && !matches!(
+                self.driver_states.get(&driver_id),
+                Some(ServiceState::Stopped | ServiceState::Exited | ServiceState::Failed)
+            )
+    }
+
+    /// Charge `count` cspace slots to `badge`, failing if that would exceed its
+    /// quota. Requests charge what they allocate and `refund_slots` on failure.
+    fn charge_slots(&mut self, badge: Badge, count: usize) -> Result<(), Error> {
+        let quota = self.config.slot_quota;
+        self.slot_usage.charge(badge.bits(), count, quota).inspect_err(|_| {
+            warn!(
+                "Client {} cannot take {} more slots, {} of {} used",
+                badge,
+                count,
+                self.slot_usage.used(badge.bits()),
+                quota
+            );
+        })
+    }
+
+    fn refund_slots(&mut self, badge: Badge, count: usize) {
+        self.slot_usage.refund(badge.bits(), count);
+    }
+
+    /// Copy `cap` into a fresh slot for a reply, giving the slot back on failure.
+    fn copy_for_reply(&mut self, cap: CapPtr) -> Result<CapPtr, Error> {
+        let slot = self.alloc_slot()?;
+        if let Err(e) = CSPACE_CAP.copy_self(cap, slot, Rights::ALL) {
+            self.free_slot(slot);
+            return Err(e);
+        }
+        Ok(slot)
+    }
+
+    /// Move the cap received in `recv` into a fresh slot.
+    fn take_cap(&mut self, recv: CapPtr) -> Result<CapPtr, Error> {
+        let slot = self.alloc_slot()?;
+        if let Err(e) = CSPACE_CAP.transfer_self(recv, slot) {
+            self.free_slot(slot);
+            return Err(e);
+        }
+        Ok(slot)
+    }
+
+    /// Mint a new cap for `[base, base + size)` and record the grant.
+    fn claim_mmio(&mut self, driver_id: usize, base: usize, size: usize) -> Result<CapPtr, Error> {
+        let slot = self.alloc_slot()?;
+        if let Err(e) = KERNEL_CAP.get_mmio(base, mmio_pages(size), slot) {
+            self.free_slot(slot);
+            return Err(e);
+        }
+        self.mmio_caps.insert(base, slot);
+        self.mmio_grants.insert(base, (size, driver_id));
+        Ok(slot)
+    }
+
+    /// Fetch IRQ line `irq_num` from the kernel and cache its handler cap.
+    fn claim_irq(&mut self, irq_num: usize) -> Result<CapPtr, Error> {
+        let slot = self.alloc_slot()?;
+        let claimed = KERNEL_CAP
+            .get_irq(irq_num, slot)
+            .and_then(|_| IRQ_CONTROL_CAP.set_priority(irq_num, 1));
+        if let Err(e) = claimed {
+            self.free_slot(slot);
+            return Err(e);
+        }
+        self.irq_caps.insert(irq_num, slot);
+        Ok(slot)
+    }
+
+    /// Mint a cap for just `[base, base + size)` into a fresh reply slot.
+    fn mint_mmio_for_reply(&mut self, base: usize, size: usize) -> Result<CapPtr, Error> {
+        let slot = self.alloc_slot()?;
+        if let Err(e) = KERNEL_CAP.get_mmio(base, mmio_pages(size), slot) {
+            self.free_slot(slot);
+            return Err(e);
+        }
+        Ok(slot)
+    }
+
+    /// Drop grants overlapping `[base, base + size)` that belong to drivers which
+    /// are gone, so the region can be handed out again. A live grant that fully
+    /// covers the request is a shared window and left alone; any other overlap
+    /// with a live driver's grant fails with `AlreadyExists`.
+    fn reclaim_stale_mmio(
+        &mut self,
+        driver_id: usize,
+        base: usize,
+        size: usize,
+    ) -> Result<(), Error> {
+        let end = base.saturating_add(size);
+        let overlapping: Vec<(usize, usize, usize)> = self
+            .mmio_grants
+            .iter()
+            .filter(|(b, (sz, owner))| {
+                *owner != driver_id && **b < end && base < b.saturating_add(*sz)
+            })
+            .map(|(b, (sz, owner))| (*b, *sz, *owner))
+            .collect();
+
+        let mut conflict = false;
+        for (grant_base, grant_size, owner) in overlapping {
+            if self.driver_alive(owner) {
+                let shared = grant_base <= base && end <= grant_base.saturating_add(grant_size);
+                if !shared {
+                    error!(
+                        "MMIO {:#x}+{:#x} requested by driver {} overlaps grant {:#x} of live driver {}",
+                        base, size, driver_id, grant_base, owner
+                    );
+                    conflict = true;
+                }
+                continue;
+            }
+            // The dead driver's copies went with its CSpace; only our cached
+            // master cap is left to drop.
+            warn!("Dropping stale MMIO grant {:#x} of dead driver {}", grant_base, owner);
+            self.mmio_grants.remove(&grant_base);
+            if let Some(slot) = self.mmio_caps.remove(&grant_base) {
+                if let Err(e) = CSPACE_CAP.delete(slot) {
+                    error!("Failed to delete MMIO cap {:?}: {:?}", slot, e);
                 }
             }
         }
-        None
+        if conflict { Err(Error::AlreadyExists) } else { Ok(()) }
+    }
+
+    pub(super) fn find_node_by_name(&self, name: &str) -> Option<DeviceId> {
+        self.tree.find_by_name(name)
     }
 }
 
@@ -139,17 +712,45 @@ impl<'a> DeviceService for UnicornManager<'a> {
             (region.base_addr, region.size, node.desc.name.clone())
         };
 
-        if let Some(&slot) = self.mmio_caps.get(&base_addr) {
-            log!("Using cached MMIO region for driver {}: base={:#x}", driver_id, base_addr);
-            let reply_slot = self.cspace_mgr.alloc(self.res_client)?;
-            CSPACE_CAP.copy_self(slot, reply_slot, Rights::ALL)?;
+        if !in_io_space(&self.config.io_space, base_addr, size) {
+            error!("MMIO {:#x}+{:#x} of {} lies outside the IO space", base_addr, size, name);
+            return Err(Error::InvalidArgs);
+        }
+        self.reclaim_stale_mmio(driver_id, base_addr, size)?;
+        // Nodes sharing a register window (e.g. a syscon) get a cap minted for
+        // their own pages only, never a copy of the whole window another
+        // driver was granted.
+        if let Some(grant_base) = covering_grant(&self.mmio_grants, base_addr, size) {
+            log!(
+                "MMIO {:#x}+{:#x} for driver {} lies in the window granted at {:#x}",
+                base_addr,
+                size,
+                driver_id,
+                grant_base
+            );
+            self.charge_slots(badge, 1)?;
+            let reply_slot = self
+                .mint_mmio_for_reply(base_addr, size)
+                .inspect_err(|_| self.refund_slots(badge, 1))?;
             return Ok((Page::from(reply_slot), base_addr, size));
         }
+        if self.mmio_caps.contains_key(&base_addr) {
+            error!(
+                "MMIO {:#x}+{:#x} of {} exceeds the region already granted at that base",
+                base_addr, size, name
+            );
+            return Err(Error::InvalidArgs);
+        }
 
-        let slot = self.cspace_mgr.alloc(self.res_client)?;
-        let pages = (size + PGSIZE - 1) / PGSIZE;
-        KERNEL_CAP.get_mmio(base_addr, pages, slot)?;
-        self.mmio_caps.insert(base_addr, slot);
+        // One slot keeps the minted cap, the other carries the reply.
+        self.charge_slots(badge, 2)?;
+        let slot = match self.claim_mmio(driver_id, base_addr, size) {
+            Ok(slot) => slot,
+            Err(e) => {
+                self.refund_slots(badge, 2);
+                return Err(e);
+            }
+        };
         log!(
             "Provided MMIO region for driver {}: base={:#x}, size={:#x}, name={}",
             driver_id,
@@ -157,39 +758,73 @@ impl<'a> DeviceService for UnicornManager<'a> {
             size,
             name
         );
-        let reply_slot = self.cspace_mgr.alloc(self.res_client)?;
-        CSPACE_CAP.copy_self(slot, reply_slot, Rights::ALL)?;
-        Ok((Page::from(reply_slot), base_addr, size))
+        match self.copy_for_reply(slot) {
+            Ok(reply_slot) => Ok((Page::from(reply_slot), base_addr, size)),
+            Err(e) => {
+                // The grant stays recorded and is reused by the next request.
+                self.refund_slots(badge, 1);
+                Err(e)
+            }
+        }
     }
 
-    fn get_irq(&mut self, badge: Badge, id: usize, _recv: CapPtr) -> Result<IrqHandler, Error> {
+    fn get_irq(&mut self, badge: Badge, id: usize, recv: CapPtr) -> Result<IrqHandler, Error> {
         let driver_id = badge.bits();
         let &node_id = self.pids.get(&driver_id).ok_or(Error::InvalidArgs)?;
 
-        let node = self.tree.get_node(node_id).ok_or(Error::InvalidArgs)?;
-        if id >= node.desc.irq.len() {
-            return Err(Error::InvalidArgs);
-        }
-        let irq_num = node.desc.irq[id];
-
-        if let Some(&slot) = self.irq_caps.get(&irq_num) {
-            log!("Using cached IRQ for driver {}: irq_num={}", driver_id, irq_num);
-            let reply_slot = self.cspace_mgr.alloc(self.res_client)?;
-            CSPACE_CAP.copy_self(slot, reply_slot, Rights::ALL)?;
-            return Ok(IrqHandler::from(reply_slot));
-        }
-
-        let slot = self.cspace_mgr.alloc(self.res_client)?;
-        // Get IRQ via Kernel Cap
-        KERNEL_CAP.get_irq(irq_num, slot)?;
+        let (irq_num, name) = {
+            let node = self.tree.get_node(node_id).ok_or(Error::InvalidArgs)?;
+            // An IRQ without a notification badge bit could never be delivered.
+            if id >= node.desc.irq.len() || irq_badge(id).is_none() {
+                return Err(Error::InvalidArgs);
+            }
+            (node.desc.irq[id], node.desc.name.clone())
+        };
+        // An endpoint slot if one was sent, the reply slot, and the cached
+        // handler slot when the line is claimed for the first time.
+        let cached = self.irq_caps.get(&irq_num).copied();
+        let needed = !is_null_cap(recv) as usize + if cached.is_some() { 1 } else { 2 };
+        self.charge_slots(badge, needed)?;
 
-        let handler = IrqHandler::from(slot);
-        IRQ_CONTROL_CAP.set_priority(irq_num, 1)?;
+        let handler = match cached {
+            Some(slot) => {
+                log!("Using cached IRQ for driver {}: irq_num={}", driver_id, irq_num);
+                Ok(slot)
+            }
+            None => self.claim_irq(irq_num).inspect(|slot| {
+                log!("Provided IRQ for driver {}: irq_num={}, slot={:?}", name, irq_num, slot)
+            }),
+        };
+        // A fresh handler stays cached even if the steps below fail.
+        let handler_slots = cached.is_none() as usize;
+        let handler = handler.inspect_err(|_| self.refund_slots(badge, needed))?;
+        let reply_slot = self
+            .copy_for_reply(handler)
+            .inspect_err(|_| self.refund_slots(badge, needed - handler_slots))?;
 
-        self.irq_caps.insert(irq_num, slot);
-        log!("Provided IRQ for driver {}: irq_num={}, slot={:?}", node.desc.name, irq_num, slot);
-        let reply_slot = self.cspace_mgr.alloc(self.res_client)?;
-        CSPACE_CAP.copy_self(handler.cap(), reply_slot, Rights::ALL)?;
+        let ep = if is_null_cap(recv) {
+            CapPtr::null()
+        } else {
+            match self.take_cap(recv) {
+                Ok(ep) => ep,
+                Err(e) => {
+                    self.free_slot(reply_slot);
+                    self.refund_slots(badge, needed - handler_slots);
+                    return Err(e);
+                }
+            }
+        };
+        // Shared lines keep one entry per device; a repeated request only swaps the endpoint.
+        let owners = self.irqs.entry(irq_num).or_default();
+        match owners.iter_mut().find(|(owner, _)| *owner == node_id) {
+            Some((_, old)) => {
+                if !is_null_cap(*old) {
+                    let _ = CSPACE_CAP.delete(*old);
+                }
+                *old = ep;
+            }
+            None => owners.push((node_id, ep)),
+        }
         Ok(IrqHandler::from(reply_slot))
     }
 
@@ -200,8 +835,24 @@ impl<'a> DeviceService for UnicornManager<'a> {
     fn report(&mut self, badge: Badge, desc: Vec<DeviceDescNode>) -> Result<(), Error> {
         let driver_id = badge.bits();
         if let Some(&node_id) = self.pids.get(&driver_id) {
+            let added: Vec<_> = desc.iter().map(|n| n.desc.name.clone()).collect();
             self.tree.mount_subtree(node_id, desc)?;
-            self.scan_subtree(node_id)
+            for name in added.iter() {
+                self.event_service.record(
+                    LogKind::DeviceAdded,
+                    name.clone(),
+                    alloc::format!("reported by driver {}", driver_id),
+                );
+            }
+            // A driver reporting children is alive and probing its device.
+            if self.tree.get_node(node_id).map(|n| n.state) == Some(DeviceState::Starting) {
+                self.set_node_state(node_id, DeviceState::Initializing, None);
+            }
+            let scanned = self.scan_subtree(node_id);
+            if !added.is_empty() {
+                self.event_service.publish(DeviceEvent::DevicesAdded { names: added });
+            }
+            scanned
         } else {
             Err(Error::InvalidArgs)
         }
@@ -215,18 +866,28 @@ impl<'a> DeviceService for UnicornManager<'a> {
             self.driver_states.get(&driver_id).copied().unwrap_or(ServiceState::Stopped);
 
         self.driver_states.insert(driver_id, status);
-        if let Some(node) = self.tree.get_node_mut(node_id) {
+        if let Some(node) = self.tree.get_node(node_id) {
             log!("Service {} transition: {:?} -> {:?}", node.desc.name, old_status, status);
-            node.state = match status {
-                ServiceState::Starting => DeviceState::Starting,
-                ServiceState::Running => DeviceState::Running,
-                ServiceState::Stopped | ServiceState::Exited | ServiceState::Failed => {
-                    DeviceState::Error
-                }
-            };
+        }
+        let (state, reason) = match status {
+            ServiceState::Starting => (DeviceState::Initializing, None),
+            ServiceState::Running => (DeviceState::Running, None),
+            ServiceState::Stopped | ServiceState::Exited | ServiceState::Failed => {
+                (DeviceState::Error, Some(alloc::format!("driver reported {:?}", status)))
+            }
+        };
+        self.set_node_state(node_id, state, reason);
+        match reported_exit(status) {
+            Some(ReportedExit::Clean) => {
+                self.reclaim_driver(driver_id);
+            }
+            Some(ReportedExit::Crashed) => self.handle_driver_exit(driver_id),
+            None => {}
         }
 
         if status == ServiceState::Running {
+            // A driver that came up again starts over with a fresh restart budget.
+            self.restart_counts.reset(node_id);
             if let Some(root) = self.tree.root {
                 self.scan_subtree(root)?;
             }
@@ -243,11 +904,8 @@ impl<'a> DeviceService for UnicornManager<'a> {
     ) -> Result<(), Error> {
         let driver_id = badge.bits();
         if let Some(&node_id) = self.pids.get(&driver_id) {
-            {
-                let node = self.tree.get_node_mut(node_id).ok_or(Error::InvalidArgs)?;
-                node.desc.compatible = compatible;
-                node.state = DeviceState::Ready;
-            }
+            self.tree.set_compatible(node_id, compatible).map_err(|_| Error::InvalidArgs)?;
+            self.set_node_state(node_id, DeviceState::Ready, None);
             self.scan_subtree(node_id)
         } else {
             Err(Error::InvalidArgs)
@@ -256,16 +914,26 @@ impl<'a> DeviceService for UnicornManager<'a> {
 
     fn register_logic(
         &mut self,
-        _badge: Badge,
+        badge: Badge,
         desc: LogicDeviceDesc,
         endpoint: CapPtr,
     ) -> Result<(), Error> {
-        let (id, _name, _ep) = self.logic_service.register(
+        let identity = self.staged_identities.remove(&badge.bits());
+        let (id, name, _ep) = self.logic_service.register(
             self.cspace_mgr,
             self.res_client,
             desc.clone(),
             endpoint,
+            identity,
         )?;
+        self.logic_service.owners.insert(id, badge.bits());
+        if desc.dev_type == LogicDeviceType::Gpio {
+            self.add_gpio_bank(id, &desc.parent_name);
+        }
+        self.event_service.publish(DeviceEvent::LogicAdded { name, dev_type: desc.dev_type });
+        if self.logic_service.take_enumeration_changed() {
+            self.event_service.publish(DeviceEvent::EnumerationChanged);
+        }
 
         if let Some(node_id) = self.find_node_by_name(&desc.parent_name) {
             if let Some(node) = self.tree.get_node_mut(node_id) {
@@ -283,24 +951,76 @@ impl<'a> DeviceService for UnicornManager<'a> {
         criteria: &str,
         _recv: CapPtr,
     ) -> Result<Endpoint, Error> {
-        self.logic_service.alloc(self.cspace_mgr, self.res_client, badge, dev_type, criteria)
+        // A compositor asking for "any" framebuffer gets the primary one.
+        let primary;
+        let criteria = if dev_type == LogicDeviceType::Fb && criteria.is_empty() {
+            primary = self.primary_fb_name().ok_or(Error::NotFound)?;
+            primary.as_str()
+        } else {
+            criteria
+        };
+        let aliased = self.resolve_alias(criteria);
+        let criteria = aliased.as_deref().unwrap_or(criteria);
+        self.charge_slots(badge, 1)?;
+        self.logic_service
+            .alloc(self.cspace_mgr, self.res_client, badge, dev_type, criteria)
+            .inspect_err(|_| self.refund_slots(badge, 1))
     }
 
     fn query(
         &mut self,
         _badge: Badge,
-        query: device::DeviceQuery,
+        mut query: device::DeviceQuery,
     ) -> Result<Vec<alloc::string::String>, Error> {
+        if let Some(resolved) = query.name.as_deref().and_then(|n| self.resolve_alias(n)) {
+            query.name = Some(resolved);
+        }
         log!(
             "Querying devices with criteria: name={:?}, compatible={:?}, dev_type={:?}",
             query.name,
             query.compatible,
             query.dev_type
         );
-        self.logic_service.query(query)
+        // Physical nodes carry no logical type, so a typed query only sees logical devices.
+        let mut results = Vec::new();
+        if query.dev_type.is_none() {
+            // Exact compatible strings are answered from the index, globs need a full walk.
+            let indexed =
+                !query.compatible.is_empty() && !query.compatible.iter().any(|c| is_glob(c));
+            let candidates: Vec<_> = if indexed {
+                let ids: BTreeSet<DeviceId> =
+                    query.compatible.iter().flat_map(|c| self.tree.find_by_compatible(c)).collect();
+                ids.into_iter().filter_map(|id| self.tree.get_node(id)).collect()
+            } else {
+                self.tree.iter_nodes().collect()
+            };
+            for node in candidates {
+                if let Some(qn) = &query.name {
+                    if !node.desc.name.contains(qn.as_str()) {
+                        continue;
+                    }
+                }
+                if !query.compatible.is_empty()
+                    && !query.compatible.iter().any(|c| {
+                        let c = normalize_compatible(c);
+                        node.desc
+                            .compatible
+                            .iter()
+                            .any(|nc| glob_match(&c, &normalize_compatible(nc)))
+                    })
+                {
+                    continue;
+                }
+                results.push(node.desc.name.clone());
+            }
+        }
+        results.extend(self.logic_service.query(query)?);
+        Ok(results)
     }
 
     fn get_desc(&mut self, _badge: Badge, name: &str) -> Result<device::DeviceDesc, Error> {
+        let aliased = self.resolve_alias(name);
+        let name = aliased.as_deref().unwrap_or(name);
         if let Some(root) = self.tree.root {
             let mut queue = VecDeque::new();
             queue.push_back(root);
@@ -315,6 +1035,11 @@ impl<'a> DeviceService for UnicornManager<'a> {
                 }
             }
         }
+        // Logical devices (disk0, net1, disk0p1 ...) have no tree node and are
+        // described by GET_LOGICAL_DESC instead.
+        if self.logic_service.get_desc(name).is_some() {
+            return Err(Error::InvalidType);
+        }
         Err(Error::NotFound)
     }
 
@@ -323,20 +1048,132 @@ impl<'a> DeviceService for UnicornManager<'a> {
         _badge: Badge,
         name: &str,
     ) -> Result<(usize, LogicDeviceDesc), Error> {
-        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
-        Ok((id as usize, desc))
+        self.logic_service.get_desc(name).ok_or(Error::NotFound)
     }
 
-    fn hook(&mut self, _badge: Badge, target: HookTarget, endpoint: CapPtr) -> Result<(), Error> {
+    fn hook(&mut self, badge: Badge, target: HookTarget, endpoint: CapPtr) -> Result<(), Error> {
         let slot = self.cspace_mgr.alloc(self.res_client)?;
         CSPACE_CAP.transfer_self(endpoint, slot)?;
         log!("Registering hook for target {:?} at endpoint {:?}", target, slot);
-        let new_hook = (target, slot);
+        let new_hook = (badge.bits(), target, slot);
         self.hooks.push(new_hook);
         Ok(())
     }
 
-    fn unhook(&mut self, _badge: Badge, _target: HookTarget) -> Result<(), Error> {
-        Err(Error::NotImplemented)
+    fn unhook(&mut self, _badge: Badge, target: HookTarget) -> Result<(), Error> {
+        let before = self.hooks.len();
+        self.hooks.retain(|(_, t, ep)| {
+            let same = same_target(t, &target);
+            if same {
+                let _ = CSPACE_CAP.delete(*ep);
+            }
+            !same
+        });
+        if self.hooks.len() == before {
+            return Err(Error::NotFound);
+        }
+        log!("Removed {} hooks for target {:?}", before - self.hooks.len(), target);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mmio_must_sit_in_the_io_space() {
+        let windows = [IoWindow { base: 0x1000_0000, size: 0x1000_0000 }];
+        assert!(in_io_space(&windows, 0x1000_0000, 0x1000));
+        assert!(in_io_space(&windows, 0x1fff_f000, 0x1000));
+        assert!(!in_io_space(&windows, 0x1fff_f000, 0x2000));
+        assert!(!in_io_space(&windows, 0x8000_0000, 0x1000));
+        assert!(!in_io_space(&windows, usize::MAX, 2));
+        assert!(in_io_space(&[], 0x8000_0000, 0x1000));
+    }
+
+    #[test]
+    fn reported_stop_or_failure_releases_the_driver() {
+        assert_eq!(reported_exit(ServiceState::Stopped), Some(ReportedExit::Clean));
+        assert_eq!(reported_exit(ServiceState::Exited), Some(ReportedExit::Clean));
+        assert_eq!(reported_exit(ServiceState::Failed), Some(ReportedExit::Crashed));
+        assert_eq!(reported_exit(ServiceState::Starting), None);
+        assert_eq!(reported_exit(ServiceState::Running), None);
+    }
+
+    #[test]
+    fn only_a_flipped_link_is_a_change() {
+        let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let up = NetStatus { mac, link_up: true };
+        let down = NetStatus { mac, link_up: false };
+        assert_eq!(link_change(None, up), None);
+        assert_eq!(link_change(Some(up), up), None);
+        assert_eq!(link_change(Some(up), down), Some(false));
+        assert_eq!(link_change(Some(down), up), Some(true));
+    }
+
+    #[test]
+    fn picked_framebuffer_is_primary() {
+        let fbs = [(3, "fb0"), (5, "fb1")];
+        assert_eq!(primary_fb(None, &fbs), Some("fb0"));
+        assert_eq!(primary_fb(Some(5), &fbs), Some("fb1"));
+        // The picked one went away.
+        assert_eq!(primary_fb(Some(5), &fbs[..1]), Some("fb0"));
+        assert_eq!(primary_fb(Some(5), &[]), None);
+    }
+
+    #[test]
+    fn hooks_fire_by_type_or_endpoint() {
+        let ep = CapPtr::null();
+        let by_type = HookTarget::Type(LogicDeviceType::Block);
+        let by_ep = HookTarget::Endpoint(ep.bits() as usize);
+        assert!(hook_fires(&by_type, ep, &LogicDeviceType::Block));
+        assert!(!hook_fires(&by_type, ep, &LogicDeviceType::Net));
+        assert!(hook_fires(&by_ep, ep, &LogicDeviceType::Net));
+        assert!(!hook_fires(
+            &HookTarget::Endpoint(ep.bits() as usize + 1),
+            ep,
+            &LogicDeviceType::Net
+        ));
+
+        // UNHOOK removes hooks on the same target only.
+        assert!(same_target(&by_type, &HookTarget::Type(LogicDeviceType::Block)));
+        assert!(!same_target(&by_type, &HookTarget::Type(LogicDeviceType::Net)));
+        assert!(!same_target(&by_type, &by_ep));
+    }
+
+    #[test]
+    fn slot_quota_is_per_client() {
+        let mut usage = SlotUsage::new();
+        assert_eq!(usage.charge(7, 3, 4), Ok(()));
+        assert_eq!(usage.charge(7, 2, 4), Err(Error::ResourceLimit));
+        assert_eq!(usage.used(7), 3);
+        // Another driver is not held back by the one at its limit.
+        assert_eq!(usage.charge(9, 4, 4), Ok(()));
+        assert_eq!(usage.charge(7, 1, 4), Ok(()));
+        assert_eq!(usage.charge(7, 1, 4), Err(Error::ResourceLimit));
+
+        usage.refund(7, 2);
+        assert_eq!(usage.charge(7, 2, 4), Ok(()));
+        // Fully refunded clients leave no entry behind.
+        usage.refund(9, 4);
+        assert!(!usage.used.contains_key(&9));
+        usage.forget(7);
+        assert!(usage.used.is_empty());
+    }
+
+    #[test]
+    fn shared_window_request_gets_only_its_pages() {
+        // A 64K syscon granted to driver 7.
+        let grants = BTreeMap::from([(0x1000_0000, (0x1_0000, 7))]);
+        assert_eq!(covering_grant(&grants, 0x1000_4000, 0x100), Some(0x1000_0000));
+        assert_eq!(covering_grant(&grants, 0x1000_f000, 0x1000), Some(0x1000_0000));
+        // What gets minted for the sharer is its own page, not the 16 of the window.
+        assert_eq!(mmio_pages(0x100), 1);
+        assert_eq!(mmio_pages(0x1_0000), 16);
+
+        assert_eq!(covering_grant(&grants, 0x1000_f000, 0x2000), None);
+        assert_eq!(covering_grant(&grants, 0x0fff_f000, 0x2000), None);
+        assert_eq!(covering_grant(&grants, usize::MAX, 2), None);
     }
 }
@@ -0,0 +1,251 @@
+use super::UnicornManager;
+use super::platform::{DeviceId, DeviceState};
+use crate::proto::{DeviceEvent, LogEntry, LogKind, NOTIFY_EVENT};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint};
+use glenda::client::ResourceClient;
+use glenda::error::Error;
+use glenda::interface::CSpaceService;
+use glenda::ipc::Badge;
+
+// Per subscriber backlog; the oldest events are dropped once it is full.
+const MAX_PENDING_EVENTS: usize = 64;
+// Size of the post-mortem event log ring.
+const MAX_LOG_ENTRIES: usize = 128;
+
+pub struct Subscriber {
+    pub endpoint: CapPtr,
+    pub mask: usize,
+    pub pending: VecDeque<DeviceEvent>,
+    pub dropped: usize,
+}
+
+impl Subscriber {
+    fn push(&mut self, event: DeviceEvent) {
+        if self.pending.len() >= MAX_PENDING_EVENTS {
+            self.pending.pop_front();
+            self.dropped += 1;
+        }
+        self.pending.push_back(event);
+    }
+}
+
+pub struct EventService {
+    pub subscribers: BTreeMap<usize, Subscriber>, // client badge -> subscriber
+    pub log: VecDeque<LogEntry>,
+    next_seq: u64,
+}
+
+impl EventService {
+    pub fn new() -> Self {
+        Self { subscribers: BTreeMap::new(), log: VecDeque::new(), next_seq: 0 }
+    }
+
+    pub fn record(&mut self, kind: LogKind, subject: String, detail: String) {
+        if self.log.len() >= MAX_LOG_ENTRIES {
+            self.log.pop_front();
+        }
+        self.log.push_back(LogEntry { seq: self.next_seq, kind, subject, detail });
+        self.next_seq += 1;
+    }
+
+    /// Last `count` log entries, oldest first. `0` returns the whole log.
+    pub fn recent(&self, count: usize) -> Vec<LogEntry> {
+        let count = if count == 0 { self.log.len() } else { count.min(self.log.len()) };
+        self.log.iter().skip(self.log.len() - count).cloned().collect()
+    }
+
+    pub fn subscribe(
+        &mut self,
+        cspace_mgr: &mut dyn CSpaceService,
+        res_client: &mut ResourceClient,
+        badge: Badge,
+        mask: usize,
+        endpoint: CapPtr,
+    ) -> Result<(), Error> {
+        if mask == 0 {
+            return Err(Error::InvalidArgs);
+        }
+        let ep = cspace_mgr.alloc(res_client)?;
+        CSPACE_CAP.transfer_self(endpoint, ep)?;
+
+        if let Some(old) = self.subscribers.remove(&badge.bits()) {
+            let _ = CSPACE_CAP.delete(old.endpoint);
+        }
+        log!("Subscriber {} registered: mask={:#x}, ep={:?}", badge, mask, ep);
+        self.subscribers.insert(
+            badge.bits(),
+            Subscriber { endpoint: ep, mask, pending: VecDeque::new(), dropped: 0 },
+        );
+        Ok(())
+    }
+
+    pub fn unsubscribe(&mut self, badge: Badge) -> Result<(), Error> {
+        let sub = self.subscribers.remove(&badge.bits()).ok_or(Error::NotFound)?;
+        let _ = CSPACE_CAP.delete(sub.endpoint);
+        Ok(())
+    }
+
+    /// Queue `event` for every subscriber whose mask takes it. Returns the
+    /// subscribers to wake up.
+    fn enqueue(&mut self, event: &DeviceEvent) -> Vec<(usize, CapPtr)> {
+        let kind = event.kind();
+        let mut woken = Vec::new();
+        for (&id, sub) in self.subscribers.iter_mut() {
+            if sub.mask & kind == 0 {
+                continue;
+            }
+            sub.push(event.clone());
+            woken.push((id, sub.endpoint));
+        }
+        woken
+    }
+
+    pub fn publish(&mut self, event: DeviceEvent) {
+        for (id, endpoint) in self.enqueue(&event) {
+            if let Err(e) = Endpoint::from(endpoint).notify(Badge::new(NOTIFY_EVENT)) {
+                warn!("Failed to notify subscriber {}: {:?}", id, e);
+            }
+        }
+    }
+
+    /// Deliver `event` to the given clients regardless of their mask.
+    /// Returns how many of them had a subscription to deliver to.
+    pub fn publish_to(&mut self, targets: &[usize], event: DeviceEvent) -> usize {
+        let mut delivered = 0;
+        for target in targets {
+            let Some(sub) = self.subscribers.get_mut(target) else {
+                continue;
+            };
+            sub.push(event.clone());
+            if let Err(e) = Endpoint::from(sub.endpoint).notify(Badge::new(NOTIFY_EVENT)) {
+                warn!("Failed to notify subscriber {}: {:?}", target, e);
+            }
+            delivered += 1;
+        }
+        delivered
+    }
+
+    pub fn poll(&mut self, badge: Badge, max: usize) -> Result<Vec<DeviceEvent>, Error> {
+        let sub = self.subscribers.get_mut(&badge.bits()).ok_or(Error::NotFound)?;
+        let count = if max == 0 { sub.pending.len() } else { max.min(sub.pending.len()) };
+        Ok(sub.pending.drain(..count).collect())
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    pub fn subscribe(&mut self, badge: Badge, mask: usize, endpoint: CapPtr) -> Result<(), Error> {
+        self.event_service.subscribe(self.cspace_mgr, self.res_client, badge, mask, endpoint)
+    }
+
+    pub fn unsubscribe(&mut self, badge: Badge) -> Result<(), Error> {
+        self.event_service.unsubscribe(badge)
+    }
+
+    pub fn poll_events(&mut self, badge: Badge, max: usize) -> Result<Vec<DeviceEvent>, Error> {
+        self.event_service.poll(badge, max)
+    }
+
+    pub fn get_event_log(&self, _badge: Badge, count: usize) -> Vec<LogEntry> {
+        self.event_service.recent(count)
+    }
+
+    /// Move a node to `state` and tell subscribers about the transition.
+    pub(super) fn set_node_state(
+        &mut self,
+        id: DeviceId,
+        state: DeviceState,
+        reason: Option<String>,
+    ) {
+        let Some(node) = self.tree.get_node_mut(id) else {
+            return;
+        };
+        let old = node.state;
+        node.state = state;
+        if old == state {
+            return;
+        }
+        let name = node.desc.name.clone();
+        let kind =
+            if state == DeviceState::Error { LogKind::DriverFailed } else { LogKind::StateChanged };
+        let detail = match &reason {
+            Some(reason) => alloc::format!("{:?} -> {:?}: {}", old, state, reason),
+            None => alloc::format!("{:?} -> {:?}", old, state),
+        };
+        self.event_service.record(kind, name.clone(), detail);
+        self.event_service.publish(DeviceEvent::StateChanged { name, old, new: state, reason });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{EVENT_DRIVER, EVENT_HOTPLUG, EVENT_STATE};
+    use glenda::protocol::device::LogicDeviceType;
+
+    fn subscribe(service: &mut EventService, badge: usize, mask: usize) {
+        let sub =
+            Subscriber { endpoint: CapPtr::null(), mask, pending: VecDeque::new(), dropped: 0 };
+        service.subscribers.insert(badge, sub);
+    }
+
+    #[test]
+    fn hotplug_subscriber_gets_the_new_names() {
+        let mut service = EventService::new();
+        subscribe(&mut service, 7, EVENT_HOTPLUG);
+        subscribe(&mut service, 9, EVENT_STATE);
+
+        let added = alloc::vec![String::from("virtio0"), String::from("virtio1")];
+        let woken = service.enqueue(&DeviceEvent::DevicesAdded { names: added.clone() });
+        assert_eq!(woken.iter().map(|(id, _)| *id).collect::<Vec<_>>(), alloc::vec![7]);
+        let logic = DeviceEvent::LogicAdded {
+            name: String::from("disk0"),
+            dev_type: LogicDeviceType::Block,
+        };
+        service.enqueue(&logic);
+
+        let events = service.poll(Badge::new(7), 0).unwrap();
+        assert!(matches!(&events[..], [
+            DeviceEvent::DevicesAdded { names },
+            DeviceEvent::LogicAdded { name, .. },
+        ] if *names == added && name == "disk0"));
+        // Not asked for hotplug events.
+        assert!(service.poll(Badge::new(9), 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn slow_subscriber_loses_the_oldest_events() {
+        let mut service = EventService::new();
+        subscribe(&mut service, 7, EVENT_HOTPLUG);
+        for i in 0..MAX_PENDING_EVENTS + 2 {
+            service.enqueue(&DeviceEvent::LogicRemoved { name: alloc::format!("net{}", i) });
+        }
+        assert_eq!(service.subscribers[&7].dropped, 2);
+        let events = service.poll(Badge::new(7), 1).unwrap();
+        assert!(matches!(&events[..], [DeviceEvent::LogicRemoved { name }] if name == "net2"));
+    }
+
+    #[test]
+    fn driver_lifecycle_goes_to_driver_subscribers() {
+        let mut service = EventService::new();
+        subscribe(&mut service, 7, EVENT_DRIVER);
+        subscribe(&mut service, 9, EVENT_HOTPLUG);
+        let device = String::from("virtio0");
+        let started = DeviceEvent::DriverStarted {
+            device: device.clone(),
+            pid: 12,
+            binary: "virtio-blk".into(),
+        };
+        assert_eq!(service.enqueue(&started).len(), 1);
+        service.enqueue(&DeviceEvent::DriverExited { device, pid: 12 });
+
+        let events = service.poll(Badge::new(7), 0).unwrap();
+        assert!(matches!(&events[..], [
+            DeviceEvent::DriverStarted { pid: 12, .. },
+            DeviceEvent::DriverExited { device, pid: 12 },
+        ] if device == "virtio0"));
+        assert!(service.poll(Badge::new(9), 0).unwrap().is_empty());
+    }
+}
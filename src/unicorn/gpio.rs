@@ -0,0 +1,191 @@
+use super::UnicornManager;
+use crate::proto::NOTIFY_GPIO;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use glenda::cap::Endpoint;
+use glenda::error::Error;
+use glenda::ipc::Badge;
+use glenda::protocol::device::LogicDeviceType;
+
+// Line count of a controller whose node carries no `ngpios` property.
+const DEFAULT_GPIO_LINES: usize = 32;
+
+/// Lines of one gpio controller as last reported by its driver, plus the
+/// writes clients queued for it. The line count is fixed at registration.
+pub struct GpioBank {
+    pub levels: Vec<bool>,
+    pub pending: Vec<(u32, bool)>,
+}
+
+impl GpioBank {
+    pub fn new(lines: usize) -> Self {
+        Self { levels: alloc::vec![false; lines], pending: Vec::new() }
+    }
+
+    pub fn get(&self, line: u32) -> Result<bool, Error> {
+        self.levels.get(line as usize).copied().ok_or(Error::InvalidArgs)
+    }
+
+    pub fn set(&mut self, line: u32, value: bool) -> Result<(), Error> {
+        let level = self.levels.get_mut(line as usize).ok_or(Error::InvalidArgs)?;
+        *level = value;
+        self.pending.retain(|(l, _)| *l != line);
+        self.pending.push((line, value));
+        Ok(())
+    }
+
+    /// Take the levels the driver read back. Writes not picked up yet still
+    /// win over the reported level.
+    pub fn report(&mut self, levels: Vec<bool>) -> Result<(), Error> {
+        if levels.len() != self.levels.len() {
+            return Err(Error::InvalidArgs);
+        }
+        self.levels = levels;
+        for (line, value) in self.pending.iter() {
+            self.levels[*line as usize] = *value;
+        }
+        Ok(())
+    }
+
+    pub fn take_writes(&mut self) -> Vec<(u32, bool)> {
+        core::mem::take(&mut self.pending)
+    }
+}
+
+pub struct GpioService {
+    pub banks: BTreeMap<usize, GpioBank>, // logic_id -> bank
+}
+
+impl GpioService {
+    pub fn new() -> Self {
+        Self { banks: BTreeMap::new() }
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    fn gpio_bank_id(&self, name: &str) -> Result<usize, Error> {
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Gpio {
+            return Err(Error::InvalidType);
+        }
+        Ok(id)
+    }
+
+    /// Create the bank of a gpio device at REGISTER_LOGIC time, sized by the
+    /// `ngpios` property of the controller node it was registered under.
+    pub(super) fn add_gpio_bank(&mut self, id: usize, parent: &str) {
+        let lines = self
+            .find_node_by_name(parent)
+            .and_then(|node| self.tree.get_node(node))
+            .and_then(|node| node.meta.properties.get("ngpios"))
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(DEFAULT_GPIO_LINES);
+        self.gpio_service.banks.insert(id, GpioBank::new(lines));
+    }
+
+    /// Called by the gpio driver that registered `name`; `levels` must cover
+    /// exactly the lines the bank was registered with.
+    pub fn report_gpio(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        levels: Vec<bool>,
+    ) -> Result<(), Error> {
+        let id = self.gpio_bank_id(name)?;
+        if self.logic_service.owners.get(&id) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        self.gpio_service.banks.get_mut(&id).ok_or(Error::NotFound)?.report(levels)
+    }
+
+    pub fn gpio_get(&self, _badge: Badge, name: &str, line: u32) -> Result<bool, Error> {
+        let id = self.gpio_bank_id(name)?;
+        self.gpio_service.banks.get(&id).ok_or(Error::NotFound)?.get(line)
+    }
+
+    /// Queue a write for the owning driver and wake it with `NOTIFY_GPIO`;
+    /// the driver collects queued writes with `take_gpio_writes`.
+    pub fn gpio_set(
+        &mut self,
+        _badge: Badge,
+        name: &str,
+        line: u32,
+        value: bool,
+    ) -> Result<(), Error> {
+        let id = self.gpio_bank_id(name)?;
+        self.gpio_service.banks.get_mut(&id).ok_or(Error::NotFound)?.set(line, value)?;
+
+        let (_, ep, _) = self.logic_service.devices.get(&id).ok_or(Error::NotFound)?;
+        if let Err(e) = Endpoint::from(*ep).notify(Badge::new(NOTIFY_GPIO)) {
+            warn!("Failed to notify gpio driver of {}: {:?}", name, e);
+        }
+        Ok(())
+    }
+
+    pub fn take_gpio_writes(
+        &mut self,
+        badge: Badge,
+        name: &str,
+    ) -> Result<Vec<(u32, bool)>, Error> {
+        let id = self.gpio_bank_id(name)?;
+        if self.logic_service.owners.get(&id) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        Ok(self.gpio_service.banks.get_mut(&id).ok_or(Error::NotFound)?.take_writes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for a gpio driver: applies queued writes to its pins and
+    /// reports the pin levels back, as it would after NOTIFY_GPIO.
+    struct FakeGpioDriver {
+        pins: Vec<bool>,
+    }
+
+    impl FakeGpioDriver {
+        fn service(&mut self, bank: &mut GpioBank) {
+            for (line, value) in bank.take_writes() {
+                self.pins[line as usize] = value;
+            }
+            bank.report(self.pins.clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn set_then_get_goes_through_the_driver() {
+        let mut bank = GpioBank::new(8);
+        let mut driver = FakeGpioDriver { pins: alloc::vec![false; 8] };
+
+        bank.set(3, true).unwrap();
+        assert_eq!(bank.get(3), Ok(true));
+        driver.service(&mut bank);
+        assert!(driver.pins[3]);
+        assert_eq!(bank.get(3), Ok(true));
+        assert!(bank.pending.is_empty());
+
+        bank.set(3, false).unwrap();
+        driver.service(&mut bank);
+        assert!(!driver.pins[3]);
+        assert_eq!(bank.get(3), Ok(false));
+    }
+
+    #[test]
+    fn lines_are_bounded_by_the_registered_count() {
+        let mut bank = GpioBank::new(4);
+        assert_eq!(bank.get(4), Err(Error::InvalidArgs));
+        assert_eq!(bank.set(4, true), Err(Error::InvalidArgs));
+        assert_eq!(bank.report(alloc::vec![false; 5]), Err(Error::InvalidArgs));
+        assert_eq!(bank.levels.len(), 4);
+    }
+
+    #[test]
+    fn pending_writes_win_over_a_stale_report() {
+        let mut bank = GpioBank::new(2);
+        bank.set(1, true).unwrap();
+        bank.report(alloc::vec![false, false]).unwrap();
+        assert_eq!(bank.get(1), Ok(true));
+    }
+}
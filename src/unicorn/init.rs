@@ -1,9 +1,13 @@
-use super::{BringupPhase, UnicornManager};
+use super::work::WorkTask;
+use super::{BringupPhase, UnicornManager, is_null_cap};
 use crate::layout::IRQ_CONTROL_CAP;
+use crate::proto::{BoundDevice, DeviceEvent, LogKind, NOTIFY_STOP, UnicornStatus};
+use crate::unicorn::matcher::{glob_match, is_glob, normalize_compatible};
 use crate::unicorn::platform::{DeviceId, DeviceSource, DeviceState};
-use alloc::collections::VecDeque;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint};
 use glenda::error::Error;
 use glenda::interface::{InitService, ProcessService};
 use glenda::ipc::Badge;
@@ -11,6 +15,98 @@ use glenda::protocol::device::{DeviceDesc, MMIORegion};
 use glenda::protocol::init::ServiceState;
 use glenda::utils::bootinfo::{BootInfo, PlatformType};
 
+#[derive(Debug)]
+pub enum DriverStartError {
+    // The node is gone from the tree.
+    NodeNotFound(DeviceId),
+    // No manifest entry matches the node.
+    NoMatch,
+    // The matched binary is not in the initrd.
+    BinaryNotFound(String),
+    SpawnFailed(Error),
+}
+
+impl DriverStartError {
+    /// Classify a failed spawn of `binary`. The process server reports a
+    /// binary missing from the initrd as NotFound.
+    fn from_spawn(binary: String, e: Error) -> Self {
+        match e {
+            Error::NotFound => Self::BinaryNotFound(binary),
+            e => Self::SpawnFailed(e),
+        }
+    }
+}
+
+// Work queue ticks (2s) drivers get to acknowledge a stop before they are cut off.
+const SHUTDOWN_GRACE_TICKS: usize = 200;
+// Work queue ticks (5s) a spawned driver may stay in Starting. Nothing tells
+// Unicorn when a process dies, so one that never reports in counts as exited.
+const START_TIMEOUT_TICKS: usize = 500;
+// Delay of the first restart in work queue ticks (100ms); doubled per attempt.
+const RESTART_BASE_TICKS: usize = 10;
+const RESTART_MAX_SHIFT: usize = 8;
+
+/// Ticks to wait before restart number `attempts + 1`, or `None` once `max`
+/// restarts were used up.
+fn restart_delay(attempts: usize, max: usize) -> Option<usize> {
+    (attempts < max).then(|| RESTART_BASE_TICKS << attempts.min(RESTART_MAX_SHIFT))
+}
+
+/// Restarts used per device node since its driver last reported Running.
+pub struct RestartBudget {
+    used: BTreeMap<DeviceId, usize>,
+}
+
+impl RestartBudget {
+    pub fn new() -> Self {
+        Self { used: BTreeMap::new() }
+    }
+
+    pub fn used(&self, id: DeviceId) -> usize {
+        self.used.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Take one of `max` restarts of `id`. Returns the delay to wait before
+    /// it, or `None` once they are used up.
+    pub fn take(&mut self, id: DeviceId, max: usize) -> Option<usize> {
+        let used = self.used(id);
+        let delay = restart_delay(used, max)?;
+        self.used.insert(id, used + 1);
+        Some(delay)
+    }
+
+    pub fn reset(&mut self, id: DeviceId) {
+        self.used.remove(&id);
+    }
+}
+
+/// Manifest arguments of the entry driver `pid` was spawned from.
+fn driver_args<'m>(
+    drivers: &'m [crate::config::DriverEntry],
+    pids: &BTreeMap<usize, DeviceId>,
+    driver_names: &BTreeMap<DeviceId, String>,
+    pid: usize,
+) -> Result<&'m [String], Error> {
+    let node_id = pids.get(&pid).ok_or(Error::InvalidArgs)?;
+    let driver = driver_names.get(node_id).ok_or(Error::NotFound)?;
+    let entry = drivers.iter().find(|d| &d.name == driver).ok_or(Error::NotFound)?;
+    Ok(&entry.args)
+}
+
+/// Drivers asked to stop in the first round: all but the one behind the
+/// heartbeat, which keeps timing the grace period until the others are gone.
+fn first_to_stop(pids: impl Iterator<Item = usize>, stop_last: Option<usize>) -> Vec<usize> {
+    pids.filter(|pid| Some(*pid) != stop_last).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchRank {
+    CompatibleGlob,
+    Compatible,
+    PciWildcard,
+    PciExact,
+}
+
 impl<'a> UnicornManager<'a> {
     fn refresh_driver_hints(&mut self) {
         let Some(root) = self.tree.root else {
@@ -44,20 +140,24 @@ impl<'a> UnicornManager<'a> {
 
     pub(super) fn init_root_platform(&mut self) -> Result<(), Error> {
         let bootinfo = unsafe { &*(crate::layout::BOOTINFO_ADDR as *const BootInfo) };
-        let (name, addr, size, source) = match bootinfo.platform_type {
-            PlatformType::ACPI => ("acpi", bootinfo.addr, bootinfo.size, DeviceSource::Acpi),
-            PlatformType::DTB => ("dtb", bootinfo.addr, bootinfo.size, DeviceSource::Dtb),
-            _ => return Ok(()),
+        let firmware = MMIORegion { base_addr: bootinfo.addr, size: bootinfo.size };
+        let (name, mmio, source) = match bootinfo.platform_type {
+            PlatformType::ACPI => ("acpi", alloc::vec![firmware], DeviceSource::Acpi),
+            PlatformType::DTB => ("dtb", alloc::vec![firmware], DeviceSource::Dtb),
+            _ if self.config.require_platform => {
+                error!("No DTB or ACPI platform found and require_platform is set");
+                return Err(Error::NotFound);
+            }
+            _ => {
+                warn!("No DTB or ACPI platform found, continuing with a synthetic root");
+                ("platform", Vec::new(), DeviceSource::Synthetic)
+            }
         };
 
         log!("Initializing root platform: {}", name);
 
-        let root_desc = DeviceDesc {
-            name: String::from(name),
-            compatible: Vec::new(),
-            mmio: alloc::vec![MMIORegion { base_addr: addr, size }],
-            irq: Vec::new(),
-        };
+        let root_desc =
+            DeviceDesc { name: String::from(name), compatible: Vec::new(), mmio, irq: Vec::new() };
         self.tree.insert_with_source(None, root_desc, source)?;
         self.bringup_phase = BringupPhase::Planning;
         let cpus = bootinfo.cpus as usize;
@@ -91,32 +191,87 @@ impl<'a> UnicornManager<'a> {
         };
 
         // Add under root node
-        self.tree.insert_with_source(self.tree.root, ramdisk_desc, DeviceSource::Runtime)?;
+        self.tree.insert_with_source(self.tree.root, ramdisk_desc, DeviceSource::Synthetic)?;
         Ok(())
     }
 
     pub(super) fn enqueue_if_absent(&mut self, id: DeviceId) {
-        if self.queued_nodes.insert(id) {
+        if !self.shutting_down && self.queued_nodes.insert(id) {
             self.spawn_queue.push_back(id);
         }
     }
 
+    /// How well `pattern` ("VVVV:DDDD", halves may be "*") matches a synthesized
+    /// `pci:vvvv:dddd` compatible string.
+    fn pci_id_rank(pattern: &str, compat: &str) -> Option<MatchRank> {
+        let (vendor, device) = compat.strip_prefix("pci:")?.split_once(':')?;
+        let (want_vendor, want_device) = pattern.split_once(':')?;
+        let half = |want: &str, have: &str| want == "*" || want.eq_ignore_ascii_case(have);
+        if !half(want_vendor, vendor) || !half(want_device, device) {
+            return None;
+        }
+        if want_vendor == "*" || want_device == "*" {
+            Some(MatchRank::PciWildcard)
+        } else {
+            Some(MatchRank::PciExact)
+        }
+    }
+
+    fn entry_rank(
+        drv: &crate::config::DriverEntry,
+        dev_name: &str,
+        dev_compat: &[String],
+    ) -> Option<MatchRank> {
+        let pci = drv
+            .pci_ids
+            .iter()
+            .flat_map(|id| dev_compat.iter().filter_map(move |c| Self::pci_id_rank(id, c)))
+            .max();
+        if pci.is_some() {
+            return pci;
+        }
+        drv.compatible_norm
+            .iter()
+            .filter_map(|pattern| {
+                let hit = core::iter::once(dev_name)
+                    .chain(dev_compat.iter().map(String::as_str))
+                    .any(|c| glob_match(pattern, &normalize_compatible(c)));
+                match (hit, is_glob(pattern)) {
+                    (false, _) => None,
+                    (true, false) => Some(MatchRank::Compatible),
+                    (true, true) => Some(MatchRank::CompatibleGlob),
+                }
+            })
+            .max()
+    }
+
     fn match_driver_entry(
         &self,
         dev_name: &str,
         dev_compat: &[String],
     ) -> Option<&crate::config::DriverEntry> {
-        for drv in &self.config.drivers {
-            if drv.compatible.iter().any(|c| c == dev_name) {
-                return Some(drv);
-            }
-            for dc in dev_compat {
-                if drv.compatible.iter().any(|c| c == dc) {
-                    return Some(drv);
+        Self::pick_driver_entry(&self.config.drivers, dev_name, dev_compat)
+    }
+
+    /// Pick the matching manifest entry with the highest `priority`. At equal
+    /// priority the more specific match wins: exact PCI ID, then wildcard PCI ID,
+    /// then exact compatible, then compatible glob. Remaining ties go to the
+    /// entry listed first.
+    fn pick_driver_entry<'d>(
+        drivers: &'d [crate::config::DriverEntry],
+        dev_name: &str,
+        dev_compat: &[String],
+    ) -> Option<&'d crate::config::DriverEntry> {
+        let mut best: Option<((i32, MatchRank), &crate::config::DriverEntry)> = None;
+        for drv in drivers {
+            if let Some(rank) = Self::entry_rank(drv, dev_name, dev_compat) {
+                let key = (drv.priority, rank);
+                if best.as_ref().is_none_or(|(k, _)| key > *k) {
+                    best = Some((key, drv));
                 }
             }
         }
-        None
+        best.map(|(_, drv)| drv)
     }
 
     pub(super) fn can_start_node(&self, id: DeviceId) -> bool {
@@ -129,14 +284,12 @@ impl<'a> UnicornManager<'a> {
         self.match_driver_entry(&node.desc.name, &node.desc.compatible).is_some()
     }
 
-    pub(super) fn start_driver(&mut self, id: DeviceId) -> Result<(), Error> {
-        if !self.can_start_node(id) {
-            return Ok(());
-        }
-
+    /// Spawn the manifest driver for a `Ready` node. Nodes in any other state are
+    /// left alone; a `Ready` node without a manifest entry yields `NoMatch`.
+    pub(super) fn start_driver(&mut self, id: DeviceId) -> Result<(), DriverStartError> {
         // 1. Get Node and clone name to release borrow
         let (drv_name, drv_compat) = {
-            let node_ref = self.tree.get_node(id).ok_or(Error::InvalidArgs)?;
+            let node_ref = self.tree.get_node(id).ok_or(DriverStartError::NodeNotFound(id))?;
             if node_ref.state != DeviceState::Ready {
                 return Ok(());
             }
@@ -144,52 +297,401 @@ impl<'a> UnicornManager<'a> {
         };
 
         // 2. Match driver
-        // Simplified matching: check by name or compatible string for now
-        // In real world, use PCI ID / Compatible string
         let (driver_name, drv_binary) =
             if let Some(entry) = self.match_driver_entry(&drv_name, &drv_compat) {
                 (entry.name.clone(), entry.binary.clone())
             } else {
-                // No driver found, ignore
-                return Ok(());
+                return Err(DriverStartError::NoMatch);
             };
 
+        // Unicorn cannot list the initrd itself, so a binary counts as missing
+        // once the process server failed to find it. Its other nodes fail here
+        // instead of spawning again.
+        if self.missing_binaries.contains(&drv_binary) {
+            let reason = alloc::format!("driver binary {} not found", drv_binary);
+            self.set_node_state(id, DeviceState::Error, Some(reason));
+            return Err(DriverStartError::BinaryNotFound(drv_binary));
+        }
+
         log!("Starting driver {} for device {}", drv_binary, id.index);
 
         match self.proc_client.spawn(Badge::null(), &drv_binary) {
             Ok(pid) => {
                 let old_status =
                     self.driver_states.get(&pid).copied().unwrap_or(ServiceState::Stopped);
-                let node = self.tree.get_node_mut(id).ok_or(Error::InvalidArgs)?;
                 self.pids.insert(pid, id);
                 self.driver_states.insert(pid, ServiceState::Starting);
+                self.driver_binaries.insert(pid, drv_binary.clone());
+                self.event_service.record(
+                    LogKind::DriverSpawned,
+                    driver_name.clone(),
+                    alloc::format!("pid {} for {}", pid, drv_name),
+                );
+                self.event_service.publish(DeviceEvent::DriverStarted {
+                    device: drv_name.clone(),
+                    pid,
+                    binary: drv_binary.clone(),
+                });
                 self.node_driver_names.insert(id, driver_name);
-                node.state = DeviceState::Starting;
+                self.set_node_state(id, DeviceState::Starting, None);
                 self.bringup_phase = BringupPhase::Probing;
                 log!(
                     "Service {} transition: {:?} -> {:?}",
-                    node.desc.name,
+                    drv_name,
                     old_status,
                     ServiceState::Starting
                 );
+                if let Err(e) =
+                    self.defer(WorkTask::StartTimeout(pid, id), Some(START_TIMEOUT_TICKS))
+                {
+                    warn!("Driver {} starts without a timeout: {:?}", pid, e);
+                }
                 Ok(())
             }
             Err(e) => {
-                let node = self.tree.get_node_mut(id).ok_or(Error::InvalidArgs)?;
-                error!("Failed to spawn driver {}: {:?}", drv_binary, e);
                 log!(
                     "Service {} transition: {:?} -> {:?}",
-                    node.desc.name,
+                    drv_name,
                     ServiceState::Starting,
                     ServiceState::Failed
                 );
-                node.state = DeviceState::Error;
+                let reason = alloc::format!("failed to spawn {}: {:?}", drv_binary, e);
+                self.set_node_state(id, DeviceState::Error, Some(reason));
                 self.bringup_phase = BringupPhase::Planning;
-                Ok(())
+                let err = DriverStartError::from_spawn(drv_binary, e);
+                if let DriverStartError::BinaryNotFound(binary) = &err {
+                    self.missing_binaries.insert(binary.clone());
+                }
+                Err(err)
             }
         }
     }
 
+    /// Start drivers for every `Ready` node that has no driver bound yet,
+    /// without rescanning the platform. Returns how many nodes got a driver.
+    pub fn bind_unbound(&mut self, _badge: Badge) -> Result<usize, Error> {
+        let Some(root) = self.tree.root else {
+            return Ok(0);
+        };
+
+        let mut unbound = Vec::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(root);
+        while let Some(id) = queue.pop_front() {
+            if let Some(node) = self.tree.get_node(id) {
+                if node.state == DeviceState::Ready && !self.pids.values().any(|n| *n == id) {
+                    unbound.push(id);
+                }
+                for child in &node.children {
+                    queue.push_back(*child);
+                }
+            }
+        }
+
+        let mut bound = 0;
+        for id in unbound {
+            match self.start_driver(id) {
+                Ok(()) => bound += 1,
+                Err(DriverStartError::NoMatch) => {}
+                Err(e) => warn!("Could not bind device {}: {:?}", id.index, e),
+            }
+        }
+        log!("Bound {} previously unbound devices", bound);
+        self.try_report_running();
+        Ok(bound)
+    }
+
+    /// DRIVER_EXITED: only the process manager, which holds our unbadged
+    /// endpoint, or the driver announcing its own exit may tear a driver down.
+    pub fn driver_exited(&mut self, badge: Badge, pid: usize) -> Result<(), Error> {
+        if badge.bits() != Badge::null().bits() && badge.bits() != pid {
+            warn!("Driver {} exit reported by {}, ignoring", pid, badge.bits());
+            return Err(Error::InvalidArgs);
+        }
+        if !self.pids.contains_key(&pid) {
+            return Err(Error::NotFound);
+        }
+        self.handle_driver_exit(pid);
+        Ok(())
+    }
+
+    /// A driver still `Starting` after `START_TIMEOUT_TICKS` crashed or hangs
+    /// in probe; either way it is torn down and restarted like an exited one.
+    pub(super) fn start_timed_out(&mut self, pid: usize, id: DeviceId) {
+        if self.pids.get(&pid) != Some(&id)
+            || self.driver_states.get(&pid) != Some(&ServiceState::Starting)
+        {
+            return;
+        }
+        error!("Driver {} did not report in within {} ticks", pid, START_TIMEOUT_TICKS);
+        self.handle_driver_exit(pid);
+    }
+
+    /// A driver crashed or was cut off: reclaim what it held and restart its
+    /// node after a backoff.
+    pub fn handle_driver_exit(&mut self, pid: usize) {
+        if let Some(node_id) = self.reclaim_driver(pid) {
+            self.schedule_restart(node_id);
+        }
+    }
+
+    /// Reclaim everything a driver held once its process is gone: its pid entry,
+    /// IRQ and MMIO caps, the logical devices it registered and what it holds
+    /// as a client. The device node is left in `Error`. Returns that node.
+    pub(super) fn reclaim_driver(&mut self, pid: usize) -> Option<DeviceId> {
+        let node_id = self.pids.remove(&pid)?;
+        let status = self.driver_states.remove(&pid);
+        let binary = self.driver_binaries.remove(&pid);
+        self.staged_identities.remove(&pid);
+        self.slot_usage.forget(pid);
+        self.thermal_zones.remove(&pid);
+        self.node_driver_names.remove(&node_id);
+        log!("Driver {} ({:?}) exited: {:?}", pid, binary, status);
+        if let Some(node) = self.tree.get_node(node_id) {
+            let device = node.desc.name.clone();
+            self.event_service.publish(DeviceEvent::DriverExited { device, pid });
+        }
+
+        // Lines only this driver still had to service would otherwise stay unacked.
+        for irq in self.irq_service.forget(pid) {
+            if let Err(e) = self.ack_irq(irq) {
+                warn!("Failed to ack IRQ {} after driver {} exited: {:?}", irq, pid, e);
+            }
+        }
+        // CSPACE_CAP offers no revoke, so deleting our slots cannot reach the
+        // copies handed to the driver; those go away with its CSpace. A driver
+        // cut off while still alive keeps them until its process is torn down.
+        // A shared line stays registered until its last device is gone.
+        let mut released = Vec::new();
+        for (irq, owners) in self.irqs.iter_mut() {
+            owners.retain(|(id, ep)| {
+                if *id != node_id {
+                    return true;
+                }
+                if !is_null_cap(*ep) {
+                    let _ = CSPACE_CAP.delete(*ep);
+                }
+                false
+            });
+            if owners.is_empty() {
+                released.push(*irq);
+            }
+        }
+        for irq in released {
+            self.irqs.remove(&irq);
+            if let Some(slot) = self.irq_caps.remove(&irq) {
+                let _ = CSPACE_CAP.delete(slot);
+            }
+        }
+
+        let grants: Vec<usize> = self
+            .mmio_grants
+            .iter()
+            .filter(|(_, (_, owner))| *owner == pid)
+            .map(|(base, _)| *base)
+            .collect();
+        // The driver's own copies died with its CSpace; drop our master caps.
+        for base in grants {
+            self.mmio_grants.remove(&base);
+            if let Some(slot) = self.mmio_caps.remove(&base) {
+                let _ = CSPACE_CAP.delete(slot);
+            }
+        }
+
+        for id in self.logic_service.owned_by(pid) {
+            self.revoke_logic(id);
+        }
+
+        // What the driver registered as a client of other devices.
+        self.hooks.retain(|(owner, _, ep)| {
+            if *owner == pid {
+                let _ = CSPACE_CAP.delete(*ep);
+            }
+            *owner != pid
+        });
+        let _ = self.event_service.unsubscribe(Badge::new(pid));
+        let _ = self.release_input_focus(Badge::new(pid));
+        self.drop_timer_consumer(pid);
+
+        if self.tree.get_node(node_id).is_some_and(|n| n.state != DeviceState::Error) {
+            let reason = alloc::format!("driver {} exited", pid);
+            self.set_node_state(node_id, DeviceState::Error, Some(reason));
+        }
+        Some(node_id)
+    }
+
+    /// Queue another start of a crashed node's driver, doubling the delay on
+    /// every attempt until the entry's `max_restarts` is used up. The heartbeat
+    /// timer wakes the run loop for it, so restarts happen on an idle system too.
+    fn schedule_restart(&mut self, id: DeviceId) {
+        if self.shutting_down {
+            return;
+        }
+        let Some(node) = self.tree.get_node(id) else {
+            return;
+        };
+        let Some(max) = self
+            .match_driver_entry(&node.desc.name, &node.desc.compatible)
+            .map(|entry| entry.max_restarts)
+        else {
+            return;
+        };
+        let Some(delay) = self.restart_counts.take(id, max) else {
+            error!("Giving up on {} after {} restarts", node.desc.name, max);
+            return;
+        };
+        let attempt = self.restart_counts.used(id);
+        log!("Restarting {} in {} ticks (attempt {}/{})", node.desc.name, delay, attempt, max);
+        if let Err(e) = self.defer(WorkTask::RestartDriver(id), Some(delay)) {
+            error!("Failed to schedule restart of device {}: {:?}", id.index, e);
+        }
+    }
+
+    pub(super) fn restart_driver(&mut self, id: DeviceId) -> Result<(), DriverStartError> {
+        // The node may have been removed or revived by a manifest reload meanwhile.
+        if self.tree.get_node(id).map(|n| n.state) != Some(DeviceState::Error) {
+            return Ok(());
+        }
+        let attempt = self.restart_counts.used(id);
+        self.set_node_state(id, DeviceState::Ready, Some(alloc::format!("restart {}", attempt)));
+        self.start_driver(id)
+    }
+
+    /// Ask every spawned driver to stop and keep serving until all of them
+    /// acknowledged or `SHUTDOWN_GRACE_TICKS` passed; `advance_shutdown` drives
+    /// the rest from the run loop. The driver behind the heartbeat timer is
+    /// asked last, since the grace period is measured with its ticks. Without
+    /// a timer every run loop round counts as a tick, so the wait stays bounded
+    /// by the messages the stopping drivers send.
+    pub fn shutdown_all(&mut self) {
+        if self.shutting_down {
+            return;
+        }
+        log!("Shutting down {} drivers", self.pids.len());
+        self.shutting_down = true;
+        self.spawn_queue.clear();
+        self.queued_nodes.clear();
+        self.work_queue.clear();
+
+        self.stop_last = self
+            .heartbeat_device()
+            .and_then(|(device, _)| self.logic_service.owners.get(&device).copied());
+        for pid in first_to_stop(self.pids.keys().copied(), self.stop_last) {
+            self.request_stop(pid);
+        }
+        if let Err(e) = self.defer(WorkTask::ForceStop, Some(SHUTDOWN_GRACE_TICKS)) {
+            warn!("Cannot time the stop grace period, reclaiming now: {:?}", e);
+            self.force_stop();
+        }
+        self.advance_shutdown();
+    }
+
+    /// Send NOTIFY_STOP on every endpoint we hold for `pid`: its logical
+    /// devices, its IRQ forwarding endpoints and its event subscription.
+    fn request_stop(&self, pid: usize) {
+        let mut endpoints: Vec<(CapPtr, String)> = Vec::new();
+        for id in self.logic_service.owned_by(pid) {
+            if let Some((_, ep, name)) = self.logic_service.devices.get(&id) {
+                endpoints.push((*ep, name.clone()));
+            }
+        }
+        if let Some(&node_id) = self.pids.get(&pid) {
+            for (irq, owners) in self.irqs.iter() {
+                let eps = owners.iter().filter(|(id, _)| *id == node_id);
+                endpoints.extend(eps.map(|(_, ep)| (*ep, alloc::format!("IRQ {}", irq))));
+            }
+        }
+        if let Some(sub) = self.event_service.subscribers.get(&pid) {
+            endpoints.push((sub.endpoint, String::from("event subscription")));
+        }
+
+        let mut asked = false;
+        for (ep, via) in endpoints {
+            if is_null_cap(ep) {
+                continue;
+            }
+            match Endpoint::from(ep).notify(Badge::new(NOTIFY_STOP)) {
+                Ok(()) => asked = true,
+                Err(e) => warn!("Failed to ask driver {} to stop via {}: {:?}", pid, via, e),
+            }
+        }
+        if !asked {
+            warn!("Driver {} has no endpoint to be asked to stop", pid);
+        }
+    }
+
+    /// Called from the run loop while shutting down: stop the heartbeat driver
+    /// once the others are gone, and leave the loop once every driver is.
+    pub(super) fn advance_shutdown(&mut self) {
+        if self.pids.keys().any(|pid| Some(*pid) != self.stop_last) {
+            return;
+        }
+        if let Some(last) = self.stop_last.take() {
+            if self.pids.contains_key(&last) {
+                self.request_stop(last);
+                self.work_queue.clear();
+                let _ = self.defer(WorkTask::ForceStop, Some(SHUTDOWN_GRACE_TICKS));
+                return;
+            }
+        }
+
+        // Logical devices whose driver was never tracked by pid.
+        let remaining: Vec<usize> = self.logic_service.devices.keys().copied().collect();
+        for id in remaining {
+            self.revoke_logic(id);
+        }
+        self.work_queue.clear();
+        self.ipc.running = false;
+        log!("All drivers stopped");
+        self.init_client.report_service(Badge::null(), ServiceState::Stopped).unwrap_or_else(|e| {
+            error!("Failed to report stopped state: {:?}", e);
+        });
+    }
+
+    /// Grace period over: reclaim whatever the drivers that did not acknowledge
+    /// still hold through us. The process manager is told through the log; we
+    /// have no way to end the process itself.
+    pub(super) fn force_stop(&mut self) {
+        for pid in first_to_stop(self.pids.keys().copied(), self.stop_last) {
+            error!("Driver {} did not stop in time, reclaiming its resources", pid);
+            self.handle_driver_exit(pid);
+        }
+    }
+
+    /// Arguments from the manifest entry a driver was spawned for. The process
+    /// server's `spawn` takes nothing but the binary name, so there is no way
+    /// to hand them over at spawn; drivers fetch them with GET_DRIVER_ARGS.
+    pub fn get_driver_args(&self, badge: Badge) -> Result<Vec<String>, Error> {
+        driver_args(&self.config.drivers, &self.pids, &self.node_driver_names, badge.bits())
+            .map(<[String]>::to_vec)
+    }
+
+    pub fn list_devices_for_driver(&self, _badge: Badge, binary: &str) -> Vec<BoundDevice> {
+        self.driver_binaries
+            .iter()
+            .filter(|(_, bin)| bin.as_str() == binary)
+            .filter_map(|(pid, _)| self.pids.get(pid))
+            .filter_map(|id| self.tree.get_node(*id))
+            .map(|node| BoundDevice { name: node.desc.name.clone(), state: node.state })
+            .collect()
+    }
+
+    pub fn get_status(&self, _badge: Badge) -> UnicornStatus {
+        UnicornStatus {
+            phase: self.bringup_phase,
+            configured_drivers: self.config.drivers.len(),
+            spawned_drivers: self.pids.len(),
+            running_drivers: self
+                .driver_states
+                .values()
+                .filter(|state| **state == ServiceState::Running)
+                .count(),
+            blocked_nodes: if self.blocked_count == usize::MAX { 0 } else { self.blocked_count },
+            devices: self.tree.len(),
+            logic_devices: self.logic_service.devices.len(),
+        }
+    }
+
     pub(super) fn try_report_running(&mut self) {
         self.refresh_driver_hints();
 
@@ -304,3 +806,198 @@ impl<'a> UnicornManager<'a> {
         blocked
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DriverEntry;
+
+    pub(crate) fn entry(json: &str) -> DriverEntry {
+        let mut entry: DriverEntry = serde_json::from_str(json).unwrap();
+        entry.normalize();
+        entry
+    }
+
+    fn compat(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn exact_compatible_outranks_glob() {
+        let exact = entry(r#"{"name":"net","binary":"net","compatible":["virtio,net"]}"#);
+        let glob = entry(r#"{"name":"any","binary":"any","compatible":["virtio,*"]}"#);
+        let dev = compat(&["virtio,net"]);
+
+        let exact_rank = UnicornManager::entry_rank(&exact, "net0", &dev).unwrap();
+        let glob_rank = UnicornManager::entry_rank(&glob, "net0", &dev).unwrap();
+        assert_eq!(exact_rank, MatchRank::Compatible);
+        assert_eq!(glob_rank, MatchRank::CompatibleGlob);
+        assert!(exact_rank > glob_rank);
+        assert!(UnicornManager::entry_rank(&exact, "blk0", &compat(&["virtio,blk"])).is_none());
+    }
+
+    #[test]
+    fn exact_pci_id_outranks_wildcard() {
+        let exact =
+            entry(r#"{"name":"e1000","binary":"e1000","compatible":[],"pci_ids":["8086:100E"]}"#);
+        let vendor =
+            entry(r#"{"name":"intel","binary":"intel","compatible":[],"pci_ids":["8086:*"]}"#);
+        let dev = compat(&["pci:8086:100e", "pci,class,020000"]);
+
+        let exact_rank = UnicornManager::entry_rank(&exact, "pci0", &dev).unwrap();
+        let vendor_rank = UnicornManager::entry_rank(&vendor, "pci0", &dev).unwrap();
+        assert_eq!(exact_rank, MatchRank::PciExact);
+        assert_eq!(vendor_rank, MatchRank::PciWildcard);
+        assert!(exact_rank > vendor_rank);
+        assert!(UnicornManager::entry_rank(&exact, "pci1", &compat(&["pci:8086:10d3"])).is_none());
+        assert_eq!(
+            UnicornManager::entry_rank(&vendor, "pci1", &compat(&["pci:8086:10d3"])),
+            Some(MatchRank::PciWildcard)
+        );
+    }
+
+    #[test]
+    fn unmatched_pci_ids_fall_back_to_compatible() {
+        let drv = entry(
+            r#"{"name":"net","binary":"net","compatible":["virtio,net"],"pci_ids":["1af4:1041"]}"#,
+        );
+        let pci_dev = compat(&["pci:1af4:1041"]);
+        let mmio_dev = compat(&["virtio,net"]);
+
+        assert_eq!(UnicornManager::entry_rank(&drv, "pci0", &pci_dev), Some(MatchRank::PciExact));
+        assert_eq!(
+            UnicornManager::entry_rank(&drv, "net0", &mmio_dev),
+            Some(MatchRank::Compatible)
+        );
+        assert!(UnicornManager::entry_rank(&drv, "pci1", &compat(&["pci:1af4:1042"])).is_none());
+
+        // At equal priority the PCI match beats a compatible-only entry.
+        let drivers = alloc::vec![
+            entry(r#"{"name":"generic","binary":"gen","compatible":["pci:1af4:1041"]}"#),
+            drv.clone(),
+        ];
+        let picked = UnicornManager::pick_driver_entry(&drivers, "pci0", &pci_dev).unwrap();
+        assert_eq!(picked.name, "net");
+    }
+
+    #[test]
+    fn spawn_failures_are_told_apart() {
+        let missing = DriverStartError::from_spawn("uart".to_string(), Error::NotFound);
+        assert!(matches!(missing, DriverStartError::BinaryNotFound(ref b) if b == "uart"));
+        let failed = DriverStartError::from_spawn("uart".to_string(), Error::OutOfMemory);
+        assert!(matches!(failed, DriverStartError::SpawnFailed(Error::OutOfMemory)));
+    }
+
+    #[test]
+    fn higher_priority_generic_driver_wins() {
+        let drivers = alloc::vec![
+            entry(r#"{"name":"stock","binary":"stock","compatible":["virtio,*"]}"#),
+            entry(r#"{"name":"override","binary":"ovr","compatible":["virtio,*"],"priority":5}"#),
+        ];
+        let dev = compat(&["virtio,net"]);
+        let picked = UnicornManager::pick_driver_entry(&drivers, "net0", &dev).unwrap();
+        assert_eq!(picked.name, "override");
+    }
+
+    #[test]
+    fn specificity_breaks_equal_priority() {
+        let drivers = alloc::vec![
+            entry(r#"{"name":"generic","binary":"gen","compatible":["virtio,*"]}"#),
+            entry(r#"{"name":"net","binary":"net","compatible":["virtio,net"]}"#),
+            entry(r#"{"name":"late","binary":"late","compatible":["virtio,net"]}"#),
+        ];
+        let dev = compat(&["virtio,net"]);
+        let picked = UnicornManager::pick_driver_entry(&drivers, "net0", &dev).unwrap();
+        // Exact beats glob; between the two exact entries the first listed wins.
+        assert_eq!(picked.name, "net");
+    }
+
+    #[test]
+    fn restart_backoff_doubles_then_gives_up() {
+        let max = 4;
+        let delays: Vec<usize> = (0..).map_while(|attempts| restart_delay(attempts, max)).collect();
+        assert_eq!(delays, alloc::vec![10, 20, 40, 80]);
+        assert_eq!(restart_delay(max, max), None);
+        assert_eq!(restart_delay(0, 0), None);
+        // The shift is capped so a large max_restarts cannot overflow.
+        assert_eq!(restart_delay(60, 100), Some(RESTART_BASE_TICKS << RESTART_MAX_SHIFT));
+    }
+
+    #[test]
+    fn exited_driver_is_restarted_after_backoff() {
+        use crate::unicorn::platform::{DeviceTree, tests::desc};
+        use crate::unicorn::work::WorkQueue;
+
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let dev = tree.insert(Some(root), desc("net0")).unwrap();
+        let mut budget = RestartBudget::new();
+        let mut queue = WorkQueue::new();
+
+        // Each exit queues a restart that comes due only after its doubled delay.
+        for expected in [10, 20] {
+            let delay = budget.take(dev, 2).unwrap();
+            assert_eq!(delay, expected);
+            queue.push(WorkTask::RestartDriver(dev), Some(delay)).unwrap();
+            for _ in 1..delay {
+                queue.tick();
+                assert!(queue.take_due().is_empty());
+            }
+            queue.tick();
+            assert_eq!(queue.take_due(), alloc::vec![WorkTask::RestartDriver(dev)]);
+        }
+        // The third exit finds the budget spent; reporting Running refills it.
+        assert_eq!(budget.take(dev, 2), None);
+        assert_eq!(budget.used(dev), 2);
+        budget.reset(dev);
+        assert_eq!(budget.take(dev, 2), Some(10));
+    }
+
+    #[test]
+    fn heartbeat_driver_stops_last() {
+        assert_eq!(first_to_stop([3, 5, 8].into_iter(), Some(5)), alloc::vec![3, 8]);
+        assert_eq!(first_to_stop([3, 5, 8].into_iter(), None), alloc::vec![3, 5, 8]);
+        // Once the others are gone only the heartbeat driver is left to ask.
+        assert!(first_to_stop([5].into_iter(), Some(5)).is_empty());
+    }
+
+    #[test]
+    fn grace_period_is_bounded_without_a_timer() {
+        use crate::unicorn::work::WorkQueue;
+
+        // Without a heartbeat each run loop round ticks the queue once.
+        let mut queue = WorkQueue::new();
+        queue.push(WorkTask::ForceStop, Some(SHUTDOWN_GRACE_TICKS)).unwrap();
+        for _ in 1..SHUTDOWN_GRACE_TICKS {
+            queue.tick();
+            assert!(queue.take_due().is_empty());
+        }
+        queue.tick();
+        assert_eq!(queue.take_due(), alloc::vec![WorkTask::ForceStop]);
+    }
+
+    #[test]
+    fn driver_args_follow_the_spawning_pid() {
+        use crate::unicorn::platform::{DeviceTree, tests::desc};
+
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let uart = tree.insert(Some(root), desc("uart0")).unwrap();
+        let net = tree.insert(Some(root), desc("net0")).unwrap();
+        let drivers = alloc::vec![
+            entry(r#"{"name":"uart","binary":"uart","compatible":[],"args":["--baud","115200"]}"#),
+            entry(r#"{"name":"net","binary":"net","compatible":[]}"#),
+        ];
+        let pids = BTreeMap::from([(11, uart), (12, net)]);
+        let names = BTreeMap::from([(uart, "uart".to_string()), (net, "net".to_string())]);
+
+        let args = |drivers: &[DriverEntry], pid| {
+            driver_args(drivers, &pids, &names, pid).map(<[String]>::to_vec)
+        };
+        assert_eq!(args(&drivers, 11), Ok(alloc::vec!["--baud".to_string(), "115200".to_string()]));
+        assert_eq!(args(&drivers, 12), Ok(Vec::new()));
+        assert_eq!(args(&drivers, 13), Err(Error::InvalidArgs));
+        // The entry went away with a manifest reload.
+        assert_eq!(args(&drivers[1..], 11), Err(Error::NotFound));
+    }
+}
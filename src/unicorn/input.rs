@@ -0,0 +1,164 @@
+use super::UnicornManager;
+use crate::proto::{InputEvent, NOTIFY_INPUT};
+use alloc::collections::VecDeque;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint};
+use glenda::error::Error;
+use glenda::ipc::Badge;
+use glenda::protocol::device::LogicDeviceType;
+
+// Events kept for the focused consumer before the oldest are dropped.
+const MAX_PENDING_INPUT: usize = 256;
+
+pub struct InputTarget {
+    pub badge: usize,
+    pub endpoint: CapPtr,
+    pub exclusive: bool,
+}
+
+/// Routes events pushed by input drivers to a single consumer. An exclusive
+/// target (grab) cannot be replaced by anyone else until it lets go.
+pub struct InputService {
+    pub target: Option<InputTarget>,
+    pub pending: VecDeque<InputEvent>,
+    pub dropped: usize,
+}
+
+impl InputService {
+    pub fn new() -> Self {
+        Self { target: None, pending: VecDeque::new(), dropped: 0 }
+    }
+
+    fn clear_target(&mut self) {
+        if let Some(old) = self.target.take() {
+            let _ = CSPACE_CAP.delete(old.endpoint);
+        }
+        self.pending.clear();
+        self.dropped = 0;
+    }
+
+    /// Queue `events` from input device `device` for the focused consumer and
+    /// return its endpoint to wake, or `None` while nobody has focus.
+    fn enqueue(&mut self, device: &str, events: Vec<InputEvent>) -> Option<(usize, CapPtr)> {
+        let target = self.target.as_ref()?;
+        for mut event in events {
+            if self.pending.len() >= MAX_PENDING_INPUT {
+                self.pending.pop_front();
+                self.dropped += 1;
+            }
+            event.device = device.to_string();
+            self.pending.push_back(event);
+        }
+        Some((target.badge, target.endpoint))
+    }
+
+    fn take(&mut self, badge: usize, max: usize) -> Result<Vec<InputEvent>, Error> {
+        match &self.target {
+            Some(target) if target.badge == badge => {}
+            _ => return Err(Error::NotFound),
+        }
+        let count = if max == 0 { self.pending.len() } else { max.min(self.pending.len()) };
+        Ok(self.pending.drain(..count).collect())
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    pub fn set_input_focus(
+        &mut self,
+        badge: Badge,
+        endpoint: CapPtr,
+        exclusive: bool,
+    ) -> Result<(), Error> {
+        if let Some(target) = &self.input_service.target {
+            if target.exclusive && target.badge != badge.bits() {
+                return Err(Error::InvalidArgs);
+            }
+        }
+        let ep = self.alloc_slot()?;
+        if let Err(e) = CSPACE_CAP.transfer_self(endpoint, ep) {
+            self.free_slot(ep);
+            return Err(e);
+        }
+        self.input_service.clear_target();
+        log!("Input focus -> {} (exclusive: {})", badge, exclusive);
+        self.input_service.target =
+            Some(InputTarget { badge: badge.bits(), endpoint: ep, exclusive });
+        Ok(())
+    }
+
+    pub fn release_input_focus(&mut self, badge: Badge) -> Result<(), Error> {
+        match &self.input_service.target {
+            Some(target) if target.badge == badge.bits() => {
+                self.input_service.clear_target();
+                Ok(())
+            }
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    /// Called by input drivers. Events are dropped while nobody has focus.
+    pub fn push_input(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        events: Vec<InputEvent>,
+    ) -> Result<usize, Error> {
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Input {
+            return Err(Error::InvalidType);
+        }
+        if self.logic_service.owners.get(&id) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        let count = events.len();
+        let Some((consumer, endpoint)) = self.input_service.enqueue(name, events) else {
+            return Ok(0);
+        };
+        if let Err(e) = Endpoint::from(endpoint).notify(Badge::new(NOTIFY_INPUT)) {
+            warn!("Failed to notify input consumer {}: {:?}", consumer, e);
+        }
+        Ok(count)
+    }
+
+    pub fn poll_input(&mut self, badge: Badge, max: usize) -> Result<Vec<InputEvent>, Error> {
+        self.input_service.take(badge.bits(), max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: u16, value: i32) -> InputEvent {
+        InputEvent { device: alloc::string::String::new(), kind: 1, code, value }
+    }
+
+    #[test]
+    fn two_drivers_feed_one_consumer() {
+        let mut service = InputService::new();
+        assert!(service.enqueue("input0", alloc::vec![key(30, 1)]).is_none());
+
+        service.target = Some(InputTarget { badge: 7, endpoint: CapPtr::null(), exclusive: false });
+        let woken = service.enqueue("input0", alloc::vec![key(30, 1), key(30, 0)]);
+        assert_eq!(woken.map(|(badge, _)| badge), Some(7));
+        service.enqueue("input1", alloc::vec![key(272, 1)]);
+
+        assert!(matches!(service.take(8, 0), Err(Error::NotFound)));
+        let events = service.take(7, 0).unwrap();
+        let seen: Vec<(&str, u16, i32)> =
+            events.iter().map(|e| (e.device.as_str(), e.code, e.value)).collect();
+        assert_eq!(seen, alloc::vec![("input0", 30, 1), ("input0", 30, 0), ("input1", 272, 1)]);
+        assert!(service.take(7, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn oldest_events_are_dropped_when_full() {
+        let mut service = InputService::new();
+        service.target = Some(InputTarget { badge: 1, endpoint: CapPtr::null(), exclusive: false });
+        let events = (0..MAX_PENDING_INPUT as u16 + 2).map(|code| key(code, 1)).collect();
+        service.enqueue("input0", events);
+        assert_eq!(service.dropped, 2);
+        assert_eq!(service.take(1, 1).unwrap()[0].code, 2);
+    }
+}
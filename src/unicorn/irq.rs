@@ -0,0 +1,157 @@
+use super::{UnicornManager, is_null_cap};
+use crate::proto::irq_badge;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use glenda::cap::{Endpoint, IrqHandler};
+use glenda::error::Error;
+use glenda::ipc::Badge;
+
+/// Lines forwarded to drivers and not acked yet. A line is acked on the
+/// controller only once every driver notified for it reported IRQ_DONE, so a
+/// level-triggered line shared by several devices stays quiet while one of
+/// them is still servicing its device.
+pub struct IrqService {
+    pending: BTreeMap<usize, BTreeSet<usize>>, // irq_num -> driver badges still servicing it
+}
+
+impl IrqService {
+    pub fn new() -> Self {
+        Self { pending: BTreeMap::new() }
+    }
+
+    pub fn is_pending(&self, irq: usize) -> bool {
+        self.pending.contains_key(&irq)
+    }
+
+    /// Wait for `drivers` to service `irq`. Returns whether the line can be
+    /// acked right away because nobody was notified.
+    pub fn fire(&mut self, irq: usize, drivers: BTreeSet<usize>) -> bool {
+        if drivers.is_empty() {
+            return true;
+        }
+        self.pending.insert(irq, drivers);
+        false
+    }
+
+    /// `driver` is done with `irq`. Returns whether it was the last one.
+    pub fn done(&mut self, irq: usize, driver: usize) -> Result<bool, Error> {
+        let waiting = self.pending.get_mut(&irq).ok_or(Error::NotFound)?;
+        if !waiting.remove(&driver) {
+            return Err(Error::InvalidArgs);
+        }
+        if !waiting.is_empty() {
+            return Ok(false);
+        }
+        self.pending.remove(&irq);
+        Ok(true)
+    }
+
+    /// Stop waiting on an exited driver. Returns the lines nobody else holds up.
+    pub fn forget(&mut self, driver: usize) -> Vec<usize> {
+        let mut released = Vec::new();
+        self.pending.retain(|&irq, waiting| {
+            if waiting.remove(&driver) && waiting.is_empty() {
+                released.push(irq);
+                return false;
+            }
+            true
+        });
+        released
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    /// Forward `irq` to every driver on the line. It is acked in `irq_done`
+    /// once they all serviced their device.
+    pub fn handle_irq(&mut self, irq: usize) -> Result<(), Error> {
+        if !self.irq_caps.contains_key(&irq) {
+            log!("Unknown IRQ notification: {}", irq);
+            return Ok(());
+        }
+        if self.irq_service.is_pending(irq) {
+            warn!("IRQ {} fired again before its drivers were done", irq);
+            return Ok(());
+        }
+        let mut notified = BTreeSet::new();
+        for (node_id, ep) in self.irqs.get(&irq).into_iter().flatten() {
+            if is_null_cap(*ep) {
+                continue;
+            }
+            let Some((&driver, _)) = self.pids.iter().find(|(_, id)| *id == node_id) else {
+                continue;
+            };
+            let Some(badge) = self
+                .tree
+                .get_node(*node_id)
+                .and_then(|node| node.desc.irq.iter().position(|&n| n == irq))
+                .and_then(irq_badge)
+            else {
+                continue;
+            };
+            match Endpoint::from(*ep).notify(Badge::new(badge)) {
+                Ok(_) => {
+                    notified.insert(driver);
+                }
+                Err(e) => error!("Failed to forward IRQ {}: {:?}", irq, e),
+            }
+        }
+        if notified.is_empty() {
+            log!("IRQ {} received without a driver endpoint", irq);
+        }
+        if self.irq_service.fire(irq, notified) {
+            self.ack_irq(irq)?;
+        }
+        Ok(())
+    }
+
+    /// IRQ_DONE: the calling driver serviced IRQ `id` of its device.
+    pub fn irq_done(&mut self, badge: Badge, id: usize) -> Result<(), Error> {
+        let &node_id = self.pids.get(&badge.bits()).ok_or(Error::InvalidArgs)?;
+        let node = self.tree.get_node(node_id).ok_or(Error::InvalidArgs)?;
+        let irq = *node.desc.irq.get(id).ok_or(Error::InvalidArgs)?;
+        if self.irq_service.done(irq, badge.bits())? {
+            self.ack_irq(irq)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn ack_irq(&self, irq: usize) -> Result<(), Error> {
+        let &slot = self.irq_caps.get(&irq).ok_or(Error::NotFound)?;
+        IrqHandler::from(slot).ack()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_line_waits_for_every_driver() {
+        let mut service = IrqService::new();
+        assert!(!service.fire(33, BTreeSet::from([7, 9])));
+        assert!(service.is_pending(33));
+
+        assert_eq!(service.done(33, 7), Ok(false));
+        assert_eq!(service.done(33, 7), Err(Error::InvalidArgs));
+        assert_eq!(service.done(33, 9), Ok(true));
+        assert!(!service.is_pending(33));
+        assert_eq!(service.done(33, 9), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn unforwarded_line_is_acked_at_once() {
+        let mut service = IrqService::new();
+        assert!(service.fire(5, BTreeSet::new()));
+        assert!(!service.is_pending(5));
+    }
+
+    #[test]
+    fn exited_driver_no_longer_holds_the_line() {
+        let mut service = IrqService::new();
+        service.fire(33, BTreeSet::from([7, 9]));
+        service.fire(34, BTreeSet::from([7]));
+        assert_eq!(service.forget(7), alloc::vec![34]);
+        assert!(service.is_pending(33));
+        assert_eq!(service.done(33, 9), Ok(true));
+    }
+}
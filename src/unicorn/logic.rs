@@ -1,4 +1,7 @@
-use alloc::collections::BTreeMap;
+use super::is_null_cap;
+use crate::proto::{NOTIFY_REVOKED, logic_requires_endpoint};
+use crate::unicorn::matcher::glob_match;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint, Rights};
@@ -7,7 +10,9 @@ use glenda::error::Error;
 use glenda::interface::CSpaceService;
 use glenda::ipc::Badge;
 use glenda::protocol::device::{self, DeviceQuery, LogicDeviceDesc, LogicDeviceType};
+use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LogicDeviceCounter {
     pub disk: usize,
     pub net: usize,
@@ -40,14 +45,224 @@ impl Default for LogicDeviceCounter {
     }
 }
 
+// Bits of a handle's wire form below the generation.
+const HANDLE_GEN_SHIFT: u32 = 32;
+
+/// Generation-tagged reference to a logical device, analogous to `DeviceId`.
+/// The generation counts earlier registrations under the same name, so a
+/// handle to `disk0` does not resolve to a later device that took the name.
+/// On the wire the generation sits in the high bits; generation 0 encodes as
+/// the raw id older clients already use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogicDeviceHandle {
+    pub id: usize,
+    pub generation: u32,
+}
+
+impl LogicDeviceHandle {
+    pub fn bits(&self) -> usize {
+        self.id | ((self.generation as usize) << HANDLE_GEN_SHIFT)
+    }
+
+    pub fn from_bits(bits: usize) -> Self {
+        Self {
+            id: bits & ((1 << HANDLE_GEN_SHIFT) - 1),
+            generation: (bits >> HANDLE_GEN_SHIFT) as u32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandleError {
+    /// The device existed but is gone or was re-registered since.
+    Stale,
+    /// No device was ever issued this id.
+    NotFound,
+}
+
+/// Stale handles answer `Stale` so clients can tell them from bad arguments
+/// and from ids that never existed.
+impl From<HandleError> for Error {
+    fn from(e: HandleError) -> Self {
+        match e {
+            HandleError::Stale => Error::Stale,
+            HandleError::NotFound => Error::NotFound,
+        }
+    }
+}
+
+/// Whether a logical device, given as (assigned name, driver's name, type),
+/// passes a QUERY. `name` matches a substring of either name, `compatible`
+/// globs against either name, and every criterion given must hold.
+fn query_matches(
+    name: Option<&str>,
+    compatible: &[String],
+    dev_type: Option<&LogicDeviceType>,
+    (assigned, desc_name, actual): (&str, &str, &LogicDeviceType),
+) -> bool {
+    if let Some(qn) = name {
+        if !assigned.contains(qn) && !desc_name.contains(qn) {
+            return false;
+        }
+    }
+    if !compatible.is_empty()
+        && !compatible.iter().any(|c| glob_match(c, assigned) || glob_match(c, desc_name))
+    {
+        return false;
+    }
+    dev_type.is_none_or(|t| t == actual)
+}
+
 pub struct LogicDeviceService {
     pub devices: BTreeMap<usize, (LogicDeviceDesc, CapPtr, String)>,
     pub counter: LogicDeviceCounter,
+    pub holders: BTreeMap<usize, BTreeSet<usize>>, // logic_id -> client badges
+    pub owners: BTreeMap<usize, usize>,            // logic_id -> driver badge
+    pub detached: BTreeSet<usize>,
+    // Highest id ever handed out. Ids are never reused, so a stale id held by a
+    // client can only miss, never alias a newer device.
+    issued: usize,
+    live: BTreeMap<usize, u32>, // logic_id -> generation of its handle
+    generations: BTreeMap<String, u32>, // assigned name -> registrations so far
+    // Names assigned on this or a previous boot, keyed by `enumeration_key`.
+    pinned: BTreeMap<String, String>,
+    keys: BTreeMap<usize, String>, // logic_id -> enumeration key
+    // `pinned` changed since the last `take_enumeration_changed`.
+    enumeration_changed: bool,
 }
 
 impl LogicDeviceService {
     pub fn new() -> Self {
-        Self { devices: BTreeMap::new(), counter: LogicDeviceCounter::default() }
+        Self {
+            devices: BTreeMap::new(),
+            counter: LogicDeviceCounter::default(),
+            holders: BTreeMap::new(),
+            owners: BTreeMap::new(),
+            detached: BTreeSet::new(),
+            issued: 0,
+            live: BTreeMap::new(),
+            generations: BTreeMap::new(),
+            pinned: BTreeMap::new(),
+            keys: BTreeMap::new(),
+            enumeration_changed: false,
+        }
+    }
+
+    /// Identity of a logical device across boots: the node it was registered
+    /// under, its type, and the hardware identity its driver staged (PCI BDF
+    /// plus serial, GPT disk GUID ...). Without one the lowest index no live
+    /// sibling of that type holds stands in, which is only stable while the
+    /// siblings register in the same order. Only types named from a counter
+    /// are worth pinning.
+    fn enumeration_key(
+        &self,
+        parent_name: &str,
+        dev_type: LogicDeviceType,
+        identity: Option<&str>,
+    ) -> Option<String> {
+        if !matches!(
+            dev_type,
+            LogicDeviceType::Block
+                | LogicDeviceType::Net
+                | LogicDeviceType::Timer
+                | LogicDeviceType::Fb
+                | LogicDeviceType::Uart
+                | LogicDeviceType::Input
+        ) {
+            return None;
+        }
+        if let Some(identity) = identity {
+            return Some(alloc::format!("{}/{:?}/id:{}", parent_name, dev_type, identity));
+        }
+        (0..)
+            .map(|index| alloc::format!("{}/{:?}/{}", parent_name, dev_type, index))
+            .find(|key| !self.keys.values().any(|live| live == key))
+    }
+
+    /// Record `name` under `key`, flagging the enumeration map as changed.
+    fn pin(&mut self, id: usize, key: String, name: &str) {
+        if self.pinned.get(&key).map(String::as_str) != Some(name) {
+            self.pinned.insert(key.clone(), name.to_string());
+            self.enumeration_changed = true;
+        }
+        self.keys.insert(id, key);
+    }
+
+    /// Whether a name was assigned that enumeration.json does not hold yet.
+    pub fn take_enumeration_changed(&mut self) -> bool {
+        core::mem::take(&mut self.enumeration_changed)
+    }
+
+    /// Name for a new device under `parent_name`: the one pinned for its
+    /// enumeration key if still free, else the next counter value. Returns the
+    /// key as well for `pin`.
+    fn assign_name(
+        &mut self,
+        parent_name: &str,
+        dev_type: LogicDeviceType,
+        identity: Option<&str>,
+    ) -> (String, Option<String>) {
+        // A device seen on a previous boot gets its old name back, unless
+        // something registered this boot already took it.
+        let key = self.enumeration_key(parent_name, dev_type, identity);
+        let pinned = key
+            .as_ref()
+            .and_then(|key| self.pinned.get(key))
+            .filter(|name| !self.devices.values().any(|(_, _, assigned)| assigned == *name))
+            .cloned();
+        let name = if let Some(name) = pinned {
+            name
+        } else {
+            match dev_type {
+                device::LogicDeviceType::Block => {
+                    let n = alloc::format!("disk{}", self.counter.disk);
+                    self.counter.disk += 1;
+                    n
+                }
+                device::LogicDeviceType::Net => {
+                    let n = alloc::format!("net{}", self.counter.net);
+                    self.counter.net += 1;
+                    n
+                }
+                device::LogicDeviceType::Volume => {
+                    let count = self
+                        .devices
+                        .values()
+                        .filter(|(d, _, _)| {
+                            matches!(d.dev_type, device::LogicDeviceType::Volume)
+                                && d.parent_name == parent_name
+                        })
+                        .count();
+                    alloc::format!("{}p{}", parent_name, count + 1)
+                }
+                device::LogicDeviceType::Timer => {
+                    let n = alloc::format!("timer{}", self.counter.timer);
+                    self.counter.timer += 1;
+                    n
+                }
+                device::LogicDeviceType::Platform => "platform".to_string(),
+                device::LogicDeviceType::Fb => {
+                    let n = alloc::format!("fb{}", self.counter.fb);
+                    self.counter.fb += 1;
+                    n
+                }
+                device::LogicDeviceType::Uart => {
+                    let n = alloc::format!("uart{}", self.counter.uart);
+                    self.counter.uart += 1;
+                    n
+                }
+                device::LogicDeviceType::Input => {
+                    let n = alloc::format!("input{}", self.counter.input);
+                    self.counter.input += 1;
+                    n
+                }
+                _ => {
+                    let n = alloc::format!("logic{}", self.counter.next_id);
+                    n
+                }
+            }
+        };
+        (name, key)
     }
 
     pub fn register(
@@ -56,117 +271,196 @@ impl LogicDeviceService {
         res_client: &mut ResourceClient,
         desc: LogicDeviceDesc,
         endpoint: CapPtr,
+        identity: Option<String>,
     ) -> Result<(usize, String, CapPtr), Error> {
-        let ep = cspace_mgr.alloc(res_client)?;
-        CSPACE_CAP.transfer_self(endpoint, ep)?;
-
-        let name = match desc.dev_type {
-            device::LogicDeviceType::Block => {
-                let n = alloc::format!("disk{}", self.counter.disk);
-                self.counter.disk += 1;
-                n
-            }
-            device::LogicDeviceType::Net => {
-                let n = alloc::format!("net{}", self.counter.net);
-                self.counter.net += 1;
-                n
-            }
-            device::LogicDeviceType::Volume => {
-                let count = self
-                    .devices
-                    .values()
-                    .filter(|(d, _, _)| {
-                        matches!(d.dev_type, device::LogicDeviceType::Volume)
-                            && d.parent_name == desc.parent_name
-                    })
-                    .count();
-                alloc::format!("{}p{}", desc.parent_name, count + 1)
-            }
-            device::LogicDeviceType::Timer => {
-                let n = alloc::format!("timer{}", self.counter.timer);
-                self.counter.timer += 1;
-                n
-            }
-            device::LogicDeviceType::Platform => "platform".to_string(),
-            device::LogicDeviceType::Fb => {
-                let n = alloc::format!("fb{}", self.counter.fb);
-                self.counter.fb += 1;
-                n
-            }
-            device::LogicDeviceType::Uart => {
-                let n = alloc::format!("uart{}", self.counter.uart);
-                self.counter.uart += 1;
-                n
-            }
-            device::LogicDeviceType::Input => {
-                let n = alloc::format!("input{}", self.counter.input);
-                self.counter.input += 1;
-                n
-            }
-            _ => {
-                let n = alloc::format!("logic{}", self.counter.next_id);
-                n
+        let ep = if is_null_cap(endpoint) {
+            if logic_requires_endpoint(desc.dev_type) {
+                error!("Logical device {} registered without an endpoint", desc.name);
+                return Err(Error::InvalidArgs);
             }
+            CapPtr::null()
+        } else {
+            let ep = cspace_mgr.alloc(res_client)?;
+            CSPACE_CAP.transfer_self(endpoint, ep)?;
+            ep
         };
 
+        let (name, key) = self.assign_name(&desc.parent_name, desc.dev_type, identity.as_deref());
         log!("Registering logical device: {} -> {:?}", name, ep);
         let id = self.counter.next_id;
         self.counter.next_id += 1;
+        self.issue_handle(id, &name);
+        if let Some(key) = key {
+            self.pin(id, key, &name);
+        }
         self.devices.insert(id, (desc.clone(), ep, name.clone()));
         Ok((id, name, ep))
     }
 
     pub fn alloc(
-        &self,
+        &mut self,
         cspace_mgr: &mut dyn CSpaceService,
         res_client: &mut ResourceClient,
         badge: Badge,
         dev_type: LogicDeviceType,
         criteria: &str,
     ) -> Result<Endpoint, Error> {
-        for (_id, (desc, ep, name)) in self.devices.iter() {
-            if desc.dev_type == dev_type && name == criteria {
+        for (id, (desc, ep, name)) in self.devices.iter() {
+            if desc.dev_type == dev_type && name == criteria && !self.detached.contains(id) {
+                // A metadata-only device has nothing to hand out.
+                if is_null_cap(*ep) {
+                    return Err(Error::InvalidType);
+                }
                 let slot = cspace_mgr.alloc(res_client)?;
                 CSPACE_CAP.mint_self(*ep, slot, badge, Rights::ALL)?;
+                self.holders.entry(*id).or_default().insert(badge.bits());
                 return Ok(Endpoint::from(slot));
             }
         }
         Err(Error::NotFound)
     }
 
-    pub fn query(&self, query: DeviceQuery) -> Result<Vec<String>, Error> {
-        let mut results = Vec::new();
-        for (_id, (desc, _ep, assigned_name)) in self.devices.iter() {
-            let mut matched = true;
+    fn issue_handle(&mut self, id: usize, name: &str) -> LogicDeviceHandle {
+        let generation = self.generations.entry(name.to_string()).or_insert(0);
+        let handle = LogicDeviceHandle { id, generation: *generation };
+        *generation = generation.wrapping_add(1);
+        self.issued = self.issued.max(id);
+        self.live.insert(id, handle.generation);
+        handle
+    }
 
-            // 1. Match by name
-            if let Some(qn) = &query.name {
-                if !assigned_name.contains(qn) && !desc.name.contains(qn) {
-                    matched = false;
-                }
-            }
+    pub fn handle(&self, id: usize) -> Option<LogicDeviceHandle> {
+        self.live.get(&id).map(|&generation| LogicDeviceHandle { id, generation })
+    }
 
-            // 2. Match by compatibility
-            if matched && !query.compatible.is_empty() {
-                if !query.compatible.iter().any(|c| assigned_name == c || *c == desc.name) {
-                    matched = false;
-                }
-            }
+    /// Id behind `handle`, provided it still names the device it was issued for.
+    pub fn resolve(&self, handle: LogicDeviceHandle) -> Result<usize, HandleError> {
+        match self.live.get(&handle.id) {
+            Some(&generation) if generation == handle.generation => Ok(handle.id),
+            Some(_) => Err(HandleError::Stale),
+            None if handle.id != 0 && handle.id <= self.issued => Err(HandleError::Stale),
+            None => Err(HandleError::NotFound),
+        }
+    }
 
-            // 3. Match by device type
-            if matched && query.dev_type.is_some() {
-                if desc.dev_type != query.dev_type.unwrap() {
-                    matched = false;
-                }
+    pub fn owned_by(&self, driver: usize) -> Vec<usize> {
+        self.owners.iter().filter(|(_, owner)| **owner == driver).map(|(id, _)| *id).collect()
+    }
+
+    /// Hide `name` from new allocations and return its id and current holders.
+    /// Only the driver that registered `id`, or a caller on the unbadged
+    /// endpoint (the process manager), may take it away from its holders.
+    pub fn may_detach(&self, id: usize, caller: Badge) -> bool {
+        caller.bits() == Badge::null().bits() || self.owners.get(&id) == Some(&caller.bits())
+    }
+
+    pub fn detach(&mut self, name: &str, caller: Badge) -> Result<(usize, Vec<usize>), Error> {
+        let id = self
+            .devices
+            .iter()
+            .find(|(_, (_, _, assigned))| assigned == name)
+            .map(|(id, _)| *id)
+            .ok_or(Error::NotFound)?;
+        if !self.may_detach(id, caller) {
+            warn!("Client {} may not detach {}", caller, name);
+            return Err(Error::InvalidArgs);
+        }
+        if !self.detached.insert(id) {
+            return Err(Error::InvalidArgs);
+        }
+        let holders =
+            self.holders.get(&id).map(|h| h.iter().copied().collect()).unwrap_or_default();
+        Ok((id, holders))
+    }
+
+    /// Drop a detached device for good. Badged copies already minted by
+    /// `alloc` live in the holders' CSpaces and cannot be pulled back from
+    /// here, so the driver is told on the device endpoint to stop answering
+    /// them before our copy is deleted.
+    pub fn revoke(&mut self, id: usize) -> Option<(LogicDeviceDesc, String)> {
+        self.detached.remove(&id);
+        self.holders.remove(&id);
+        self.owners.remove(&id);
+        self.live.remove(&id);
+        self.keys.remove(&id);
+        let (desc, ep, name) = self.devices.remove(&id)?;
+        if !is_null_cap(ep) {
+            if let Err(e) = Endpoint::from(ep).notify(Badge::new(NOTIFY_REVOKED)) {
+                warn!("Failed to tell the driver of {} it was revoked: {:?}", name, e);
             }
+            if let Err(e) = CSPACE_CAP.delete(ep) {
+                error!("Failed to delete endpoint of {}: {:?}", name, e);
+            }
+        }
+        Some((desc, name))
+    }
 
-            if matched {
+    pub fn query(&self, query: DeviceQuery) -> Result<Vec<String>, Error> {
+        let mut results = Vec::new();
+        for (id, (desc, _ep, assigned_name)) in self.devices.iter() {
+            if self.detached.contains(id) {
+                continue;
+            }
+            let candidate = (assigned_name.as_str(), desc.name.as_str(), &desc.dev_type);
+            let dev_type = query.dev_type.as_ref();
+            if query_matches(query.name.as_deref(), &query.compatible, dev_type, candidate) {
                 results.push(assigned_name.clone());
             }
         }
         Ok(results)
     }
 
+    /// Lowest counter value for `prefix` that cannot collide with an assigned
+    /// or pinned name.
+    fn counter_floor(&self, prefix: &str) -> usize {
+        self.devices
+            .values()
+            .map(|(_, _, name)| name)
+            .chain(self.pinned.values())
+            .filter_map(|name| name.strip_prefix(prefix)?.parse::<usize>().ok())
+            .map(|n| n + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn seed_counter(&mut self, seed: LogicDeviceCounter) -> Result<(), Error> {
+        let floors = [
+            (seed.disk, self.counter_floor("disk")),
+            (seed.net, self.counter_floor("net")),
+            (seed.fb, self.counter_floor("fb")),
+            (seed.uart, self.counter_floor("uart")),
+            (seed.input, self.counter_floor("input")),
+            (seed.timer, self.counter_floor("timer")),
+            (seed.next_id, self.issued + 1),
+            (seed.next_id, self.counter_floor("logic")),
+        ];
+        if floors.iter().any(|(value, floor)| value < floor) {
+            return Err(Error::InvalidArgs);
+        }
+
+        log!("Seeding logical device counters: {:?}", seed);
+        self.counter = seed;
+        Ok(())
+    }
+
+    /// Adopt names persisted by a previous boot. Counters move past every pinned
+    /// name so fresh devices never take one a returning device wants back.
+    pub fn restore_names(&mut self, map: BTreeMap<String, String>) {
+        self.pinned = map;
+        self.counter.disk = self.counter.disk.max(self.counter_floor("disk"));
+        self.counter.net = self.counter.net.max(self.counter_floor("net"));
+        self.counter.fb = self.counter.fb.max(self.counter_floor("fb"));
+        self.counter.uart = self.counter.uart.max(self.counter_floor("uart"));
+        self.counter.input = self.counter.input.max(self.counter_floor("input"));
+        self.counter.timer = self.counter.timer.max(self.counter_floor("timer"));
+    }
+
+    /// Current identity -> name map, including pinned names whose device has
+    /// not shown up this boot, for whoever persists it across reboots.
+    pub fn enumeration_map(&self) -> BTreeMap<String, String> {
+        self.pinned.clone()
+    }
+
     pub fn get_desc(&self, name: &str) -> Option<(usize, LogicDeviceDesc)> {
         for (id, (desc, _ep, assigned_name)) in self.devices.iter() {
             if assigned_name == name {
@@ -176,3 +470,158 @@ impl LogicDeviceService {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_handle_resolves() {
+        let mut service = LogicDeviceService::new();
+        let handle = service.issue_handle(1, "disk0");
+        assert_eq!(service.resolve(handle), Ok(1));
+        assert_eq!(service.handle(1), Some(handle));
+        assert_eq!(LogicDeviceHandle::from_bits(handle.bits()), handle);
+        // Generation 0 is the raw id older clients pass.
+        assert_eq!(handle.bits(), 1);
+    }
+
+    #[test]
+    fn handle_to_a_reused_name_is_stale() {
+        let mut service = LogicDeviceService::new();
+        let old = service.issue_handle(1, "disk0");
+        service.live.remove(&1);
+        let new = service.issue_handle(2, "disk0");
+
+        assert_eq!(new.generation, old.generation + 1);
+        assert_eq!(service.resolve(old), Err(HandleError::Stale));
+        assert_eq!(service.resolve(new), Ok(2));
+        // The new id with the old generation is stale as well.
+        let forged = LogicDeviceHandle { id: 2, generation: old.generation };
+        assert_eq!(service.resolve(forged), Err(HandleError::Stale));
+        assert_eq!(Error::from(HandleError::Stale), Error::Stale);
+    }
+
+    #[test]
+    fn never_issued_handle_is_not_found() {
+        let mut service = LogicDeviceService::new();
+        service.issue_handle(1, "net0");
+        let unknown = LogicDeviceHandle { id: 7, generation: 0 };
+        assert_eq!(service.resolve(unknown), Err(HandleError::NotFound));
+        let zero = LogicDeviceHandle { id: 0, generation: 0 };
+        assert_eq!(service.resolve(zero), Err(HandleError::NotFound));
+    }
+
+    #[test]
+    fn only_owner_or_unbadged_caller_may_detach() {
+        let mut service = LogicDeviceService::new();
+        service.owners.insert(1, 7);
+        assert!(service.may_detach(1, Badge::new(7)));
+        assert!(service.may_detach(1, Badge::null()));
+        assert!(!service.may_detach(1, Badge::new(9)));
+        // Nobody but the unbadged endpoint owns a device without a driver.
+        assert!(!service.may_detach(2, Badge::new(7)));
+        assert!(service.may_detach(2, Badge::null()));
+    }
+
+    #[test]
+    fn seed_below_an_assigned_name_is_rejected() {
+        let mut service = LogicDeviceService::new();
+        service
+            .restore_names(BTreeMap::from([("virtio/Block/0".to_string(), "disk2".to_string())]));
+
+        let mut seed = service.counter.clone();
+        seed.disk = 1;
+        assert_eq!(service.seed_counter(seed.clone()), Err(Error::InvalidArgs));
+        assert_eq!(service.counter.disk, 3);
+
+        seed.disk = 5;
+        assert_eq!(service.seed_counter(seed), Ok(()));
+        assert_eq!(service.counter.disk, 5);
+    }
+
+    #[test]
+    fn query_by_name_type_or_both() {
+        let disk = ("disk0", "virtio-blk", &LogicDeviceType::Block);
+        let part = ("disk0p1", "virtio-blk", &LogicDeviceType::Volume);
+        let net = ("net0", "virtio-net", &LogicDeviceType::Net);
+        let hits = |name: Option<&str>, dev_type: Option<&LogicDeviceType>| {
+            [disk, part, net]
+                .into_iter()
+                .filter(|c| query_matches(name, &[], dev_type, *c))
+                .map(|(assigned, _, _)| assigned)
+                .collect::<Vec<_>>()
+        };
+
+        // Name only: a substring of the assigned or the driver's name.
+        assert_eq!(hits(Some("disk0"), None), alloc::vec!["disk0", "disk0p1"]);
+        assert_eq!(hits(Some("virtio-net"), None), alloc::vec!["net0"]);
+        // Type only.
+        assert_eq!(hits(None, Some(&LogicDeviceType::Volume)), alloc::vec!["disk0p1"]);
+        // Both must hold.
+        assert_eq!(hits(Some("disk0"), Some(&LogicDeviceType::Block)), alloc::vec!["disk0"]);
+        assert!(hits(Some("net"), Some(&LogicDeviceType::Block)).is_empty());
+        // No criteria matches everything.
+        assert_eq!(hits(None, None).len(), 3);
+        assert!(query_matches(None, &["disk*".to_string()], None, part));
+        assert!(!query_matches(None, &["net*".to_string()], None, part));
+    }
+
+    #[test]
+    fn siblings_of_one_type_get_distinct_keys() {
+        let mut service = LogicDeviceService::new();
+        let first = service.enumeration_key("virtio", LogicDeviceType::Block, None).unwrap();
+        service.pin(1, first.clone(), "disk0");
+        let second = service.enumeration_key("virtio", LogicDeviceType::Block, None).unwrap();
+        service.pin(2, second.clone(), "disk1");
+        assert_ne!(first, second);
+        assert_eq!(service.enumeration_map().len(), 2);
+        assert!(service.take_enumeration_changed());
+        assert!(!service.take_enumeration_changed());
+        assert_eq!(service.enumeration_key("virtio", LogicDeviceType::Volume, None), None);
+    }
+
+    #[test]
+    fn restarted_device_gets_its_key_back() {
+        let mut service = LogicDeviceService::new();
+        let first = service.enumeration_key("virtio", LogicDeviceType::Block, None).unwrap();
+        service.pin(1, first.clone(), "disk0");
+        let second = service.enumeration_key("virtio", LogicDeviceType::Block, None).unwrap();
+        service.pin(2, second, "disk1");
+        service.take_enumeration_changed();
+
+        // The first driver restarts while its sibling stays registered.
+        service.keys.remove(&1);
+        assert_eq!(
+            service.enumeration_key("virtio", LogicDeviceType::Block, None),
+            Some(first.clone())
+        );
+        service.pin(3, first, "disk0");
+        assert!(!service.take_enumeration_changed());
+    }
+
+    /// Register `identity` under the ahci controller the way `register` names it.
+    fn name_disk(service: &mut LogicDeviceService, id: usize, identity: &str) -> String {
+        let (name, key) = service.assign_name("ahci", LogicDeviceType::Block, Some(identity));
+        service.pin(id, key.unwrap(), &name);
+        name
+    }
+
+    #[test]
+    fn names_follow_identity_across_boots() {
+        let mut first_boot = LogicDeviceService::new();
+        assert_eq!(name_disk(&mut first_boot, 1, "serial:AAA"), "disk0");
+        assert_eq!(name_disk(&mut first_boot, 2, "serial:BBB"), "disk1");
+        let map = first_boot.enumeration_map();
+
+        // The disks come up in the other order on the next boot.
+        let mut next_boot = LogicDeviceService::new();
+        next_boot.restore_names(map);
+        assert_eq!(name_disk(&mut next_boot, 1, "serial:BBB"), "disk1");
+        assert_eq!(name_disk(&mut next_boot, 2, "serial:AAA"), "disk0");
+        assert!(!next_boot.take_enumeration_changed());
+        // A disk never seen before does not take a pinned name.
+        assert_eq!(name_disk(&mut next_boot, 3, "serial:CCC"), "disk2");
+        assert!(next_boot.take_enumeration_changed());
+    }
+}
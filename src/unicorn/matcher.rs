@@ -0,0 +1,99 @@
+use alloc::string::String;
+
+/// Whether `pattern` uses glob syntax understood by [`glob_match`].
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of characters and
+/// `?` exactly one. Without wildcards this is plain equality.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pat = pattern.as_bytes();
+    let txt = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // (pattern pos after '*', text pos)
+
+    while t < txt.len() {
+        if p < pat.len() && (pat[p] == b'?' || pat[p] == txt[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pat.len() && pat[p] == b'*' {
+            star = Some((p + 1, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pat[p..].iter().all(|c| *c == b'*')
+}
+
+/// Canonical form of a compatible string for matching: no whitespace, ASCII
+/// lowercase, `_` spelled `-`. `ARM,PL011` and `arm, pl011` both become
+/// `arm,pl011`; glob characters pass through untouched.
+pub fn normalize_compatible(compatible: &str) -> String {
+    compatible
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| if c == '_' { '-' } else { c.to_ascii_lowercase() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_folds_case_space_and_underscore() {
+        assert_eq!(normalize_compatible("ARM,PL011"), "arm,pl011");
+        assert_eq!(normalize_compatible(" arm, pl011\t"), "arm,pl011");
+        assert_eq!(normalize_compatible("snps,dw_apb_uart"), "snps,dw-apb-uart");
+        assert_eq!(normalize_compatible("Virtio,*"), "virtio,*");
+    }
+
+    #[test]
+    fn manifest_keeps_written_compatibles() {
+        let mut manifest: crate::config::Manifest = serde_json::from_str(
+            r#"{"drivers":[{"name":"uart","binary":"uart","compatible":["ARM,PL011"]}]}"#,
+        )
+        .unwrap();
+        manifest.normalize();
+        let drv = &manifest.drivers[0];
+        assert_eq!(drv.compatible, alloc::vec!["ARM,PL011"]);
+        assert_eq!(drv.compatible_norm, alloc::vec!["arm,pl011"]);
+    }
+
+    #[test]
+    fn exact_pattern_needs_equality() {
+        assert!(glob_match("virtio,net", "virtio,net"));
+        assert!(!glob_match("virtio,net", "virtio,netx"));
+        assert!(!glob_match("virtio,net", "virtio,ne"));
+    }
+
+    #[test]
+    fn trailing_star_covers_the_family() {
+        assert!(glob_match("virtio,*", "virtio,net"));
+        assert!(glob_match("virtio,*", "virtio,blk"));
+        assert!(glob_match("virtio,*", "virtio,"));
+        assert!(!glob_match("virtio,*", "arm,pl011"));
+    }
+
+    #[test]
+    fn question_mark_is_one_char() {
+        assert!(glob_match("ns1655?a", "ns16550a"));
+        assert!(!glob_match("ns1655?a", "ns1655a"));
+        assert!(!glob_match("ns1655?a", "ns165500a"));
+    }
+
+    #[test]
+    fn no_match() {
+        assert!(!glob_match("arm,*", "virtio,net"));
+        assert!(!glob_match("", "virtio,net"));
+        assert!(glob_match("", ""));
+        assert!(!is_glob("virtio,net"));
+        assert!(is_glob("virtio,*"));
+    }
+}
@@ -1,24 +1,51 @@
 use crate::config::Manifest;
+use crate::proto::{BatteryStatus, BlockRange, FbInfo, NetStatus};
 use crate::unicorn::platform::{DeviceId, DeviceTree};
 use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
 use alloc::string::String;
 use alloc::vec::Vec;
-use glenda::cap::{CapPtr, Endpoint, Reply};
+use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint, Reply};
 use glenda::client::{InitClient, ProcessClient, ResourceClient};
 use glenda::drivers::protocol::thermal::ThermalZones;
+use glenda::error::Error;
 use glenda::protocol::device::HookTarget;
 use glenda::protocol::init::ServiceState;
 use glenda::utils::manager::{CSpaceManager, VSpaceManager};
+use serde::{Deserialize, Serialize};
 
 pub mod device;
+pub mod event;
+pub mod gpio;
 pub mod init;
+pub mod input;
+pub mod irq;
 pub mod logic;
+pub mod matcher;
 pub mod platform;
 pub mod server;
+pub mod timer;
+pub mod uart;
+pub mod vspace;
+pub mod work;
 
+use device::SlotUsage;
+use event::EventService;
+use gpio::GpioService;
+use init::RestartBudget;
+use input::InputService;
+use irq::IrqService;
 use logic::LogicDeviceService;
+use timer::TimerService;
+use uart::UartService;
+use vspace::TransientVa;
+use work::WorkQueue;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Whether `cap` is the null slot.
+pub fn is_null_cap(cap: CapPtr) -> bool {
+    cap.bits() == CapPtr::null().bits()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BringupPhase {
     Discovering,
     Planning,
@@ -46,18 +73,40 @@ pub struct UnicornManager<'a> {
     pub tree: DeviceTree,
     pub pids: BTreeMap<usize, DeviceId>, // driver_badge -> node_id
     pub driver_states: BTreeMap<usize, ServiceState>,
-    pub irqs: BTreeMap<usize, DeviceId>, // irq_num -> node_id
+    pub driver_binaries: BTreeMap<usize, String>, // driver_badge -> binary
+    pub missing_binaries: BTreeSet<String>,       // binaries the process server could not find
+    pub staged_identities: BTreeMap<usize, String>, // driver_badge -> identity of its next logic device
+    pub irqs: BTreeMap<usize, Vec<(DeviceId, CapPtr)>>, // irq_num -> (node_id, driver endpoint)
     pub irq_caps: BTreeMap<usize, CapPtr>,
+    pub irq_service: IrqService,
     pub mmio_caps: BTreeMap<usize, CapPtr>, // base_addr -> slot
+    pub mmio_grants: BTreeMap<usize, (usize, usize)>, // base_addr -> (size, driver_badge)
     pub logic_service: LogicDeviceService,
-    pub thermal_zones: BTreeMap<usize, (ThermalZones, String)>, // (zones, driver_name)
-    pub hooks: Vec<(HookTarget, CapPtr)>,
+    pub event_service: EventService,
+    pub gpio_service: GpioService,
+    pub input_service: InputService,
+    pub uart_service: UartService,
+    pub timer_service: TimerService,
+    pub thermal_zones: BTreeMap<usize, (ThermalZones, String)>, // driver_badge -> (zones, device)
+    pub battery_status: BTreeMap<usize, BatteryStatus>,         // logic_id -> last reading
+    pub net_status: BTreeMap<usize, NetStatus>,                 // logic_id -> last report
+    pub fb_info: BTreeMap<usize, FbInfo>,                       // logic_id -> current mode
+    pub block_ranges: BTreeMap<usize, BlockRange>,              // logic_id -> blocks on parent
+    pub primary_fb: Option<usize>,
+    pub hooks: Vec<(usize, HookTarget, CapPtr)>, // (client badge, target, endpoint)
     pub spawn_queue: VecDeque<DeviceId>,
     pub queued_nodes: BTreeSet<DeviceId>,
     pub node_driver_names: BTreeMap<DeviceId, String>,
+    pub restart_counts: RestartBudget,
+    pub slot_usage: SlotUsage,
     pub bringup_phase: BringupPhase,
     pub blocked_count: usize,
     pub running_reported: bool,
+    pub work_queue: WorkQueue,
+    pub transient_va: TransientVa,
+    pub shutting_down: bool,
+    pub stop_last: Option<usize>, // driver behind the heartbeat, stopped after the others
+    pub spare_slots: Vec<CapPtr>, // emptied slots handed back by `free_slot`
 }
 
 impl<'a> UnicornManager<'a> {
@@ -84,18 +133,54 @@ impl<'a> UnicornManager<'a> {
             tree: DeviceTree::new(),
             pids: BTreeMap::new(),
             driver_states: BTreeMap::new(),
+            driver_binaries: BTreeMap::new(),
+            missing_binaries: BTreeSet::new(),
+            staged_identities: BTreeMap::new(),
             irqs: BTreeMap::new(),
             irq_caps: BTreeMap::new(),
+            irq_service: IrqService::new(),
             mmio_caps: BTreeMap::new(),
+            mmio_grants: BTreeMap::new(),
             logic_service: LogicDeviceService::new(),
+            event_service: EventService::new(),
+            gpio_service: GpioService::new(),
+            input_service: InputService::new(),
+            uart_service: UartService::new(),
+            timer_service: TimerService::new(),
             thermal_zones: BTreeMap::new(),
+            battery_status: BTreeMap::new(),
+            net_status: BTreeMap::new(),
+            fb_info: BTreeMap::new(),
+            block_ranges: BTreeMap::new(),
+            primary_fb: None,
             hooks: Vec::new(),
             spawn_queue: VecDeque::new(),
             queued_nodes: BTreeSet::new(),
             node_driver_names: BTreeMap::new(),
+            restart_counts: RestartBudget::new(),
+            slot_usage: SlotUsage::new(),
             bringup_phase: BringupPhase::Discovering,
             blocked_count: usize::MAX,
             running_reported: false,
+            work_queue: WorkQueue::new(),
+            transient_va: TransientVa::default(),
+            shutting_down: false,
+            stop_last: None,
+            spare_slots: Vec::new(),
         }
     }
+
+    /// An empty CSpace slot, reusing one given back by `free_slot` first.
+    pub fn alloc_slot(&mut self) -> Result<CapPtr, Error> {
+        match self.spare_slots.pop() {
+            Some(slot) => Ok(slot),
+            None => self.cspace_mgr.alloc(self.res_client),
+        }
+    }
+
+    /// Empty `slot` and keep it for the next `alloc_slot`.
+    pub fn free_slot(&mut self, slot: CapPtr) {
+        let _ = CSPACE_CAP.delete(slot);
+        self.spare_slots.push(slot);
+    }
 }
@@ -1,9 +1,12 @@
-use alloc::collections::BTreeMap;
+use crate::proto::{DeviceListEntry, DeviceSummary, TreeNodeEntry};
+use crate::unicorn::matcher::normalize_compatible;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use glenda::error::Error;
 use glenda::protocol::device::{DeviceDesc, DeviceNodeMeta, MMIORegion};
+use serde::{Deserialize, Serialize};
 
 // 1. 强类型的 ID (句柄)
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
@@ -24,20 +27,23 @@ pub struct DeviceNode {
     pub logical_devices: Vec<usize>, // 逻辑设备列表
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum DeviceState {
-    Starting,
+    Starting,     // 驱动已启动, 尚未上报
+    Initializing, // 驱动正在探测/初始化设备
     Running,
     Ready,
     Error,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum DeviceSource {
     Unknown,
     Dtb,
     Acpi,
-    Runtime,
+    Pci,
+    Report,    // 由驱动上报
+    Synthetic, // Unicorn 自行构造 (如 ramdisk)
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -88,16 +94,29 @@ pub struct DeviceIrNode {
     pub state: DeviceState,
 }
 
+// 3. 节点槽位: 空闲槽位内嵌下一个空闲下标, 构成侵入式空闲链表
+enum DeviceSlot {
+    Occupied(DeviceNode),
+    Free(Option<u32>),
+}
+
 pub struct DeviceTree {
-    nodes: Vec<Option<DeviceNode>>,
+    nodes: Vec<DeviceSlot>,
     generations: Vec<u32>,
     free_head: Option<u32>,
-    pub root: Option<DeviceId>, // System Root (Usually "platform")
+    compat_index: BTreeMap<String, Vec<DeviceId>>, // normalized compatible -> nodes
+    pub root: Option<DeviceId>,                    // System Root (Usually "platform")
 }
 
 impl DeviceTree {
     pub const fn new() -> Self {
-        Self { nodes: Vec::new(), generations: Vec::new(), free_head: None, root: None }
+        Self {
+            nodes: Vec::new(),
+            generations: Vec::new(),
+            free_head: None,
+            compat_index: BTreeMap::new(),
+            root: None,
+        }
     }
 
     fn infer_bus(desc: &DeviceDesc) -> DeviceBus {
@@ -143,7 +162,9 @@ impl DeviceTree {
                 DeviceSource::Unknown => "src:unknown",
                 DeviceSource::Dtb => "src:dtb",
                 DeviceSource::Acpi => "src:acpi",
-                DeviceSource::Runtime => "src:runtime",
+                DeviceSource::Pci => "src:pci",
+                DeviceSource::Report => "src:report",
+                DeviceSource::Synthetic => "src:synthetic",
             }
             .to_string(),
         );
@@ -166,6 +187,20 @@ impl DeviceTree {
         }
     }
 
+    /// Reject descriptors whose MMIO regions are empty or wrap the address space.
+    fn validate_desc(desc: &DeviceDesc) -> Result<(), Error> {
+        for reg in &desc.mmio {
+            if reg.size == 0 || reg.base_addr.checked_add(reg.size).is_none() {
+                error!(
+                    "Device {} has an invalid MMIO region: base={:#x}, size={:#x}",
+                    desc.name, reg.base_addr, reg.size
+                );
+                return Err(Error::InvalidArgs);
+            }
+        }
+        Ok(())
+    }
+
     pub fn insert(
         &mut self,
         parent_id: Option<DeviceId>,
@@ -185,19 +220,26 @@ impl DeviceTree {
         desc: DeviceDesc,
         source: DeviceSource,
     ) -> Result<DeviceId, Error> {
-        // Validate parent if provided
+        // Validate parent if provided. There is exactly one root; a second
+        // parentless node would be unreachable from every traversal.
         if let Some(pid) = parent_id {
             if !self.contains(pid) {
                 return Err(Error::InvalidArgs);
             }
+        } else if self.root.is_some() {
+            return Err(Error::AlreadyExists);
         }
+        Self::validate_desc(&desc)?;
 
         let idx = if let Some(head) = self.free_head {
-            self.free_head = None; // Simplified free list logic for now
+            let DeviceSlot::Free(next) = self.nodes[head as usize] else {
+                return Err(Error::InvalidArgs);
+            };
+            self.free_head = next;
             head
         } else {
             let idx = self.nodes.len() as u32;
-            self.nodes.push(None);
+            self.nodes.push(DeviceSlot::Free(None));
             self.generations.push(0);
             idx
         };
@@ -215,27 +257,29 @@ impl DeviceTree {
             logical_devices: Vec::new(),
         };
 
-        self.nodes[idx as usize] = Some(node);
+        self.index_compatible(id, &node.desc.compatible);
+        self.nodes[idx as usize] = DeviceSlot::Occupied(node);
 
         // Link to parent
         if let Some(pid) = parent_id {
             // Use index to avoid double borrow issues with helper methods
-            if let Some(Some(p_node)) = self.nodes.get_mut(pid.index as usize) {
+            if let Some(DeviceSlot::Occupied(p_node)) = self.nodes.get_mut(pid.index as usize) {
                 if p_node.id.generation == pid.generation {
                     p_node.children.push(id);
                 }
             }
         } else {
-            if self.root.is_none() {
-                self.root = Some(id);
-            }
+            self.root = Some(id);
         }
 
         Ok(id)
     }
 
     pub fn get_node(&self, id: DeviceId) -> Option<&DeviceNode> {
-        self.nodes.get(id.index as usize)?.as_ref().filter(|n| n.id.generation == id.generation)
+        match self.nodes.get(id.index as usize)? {
+            DeviceSlot::Occupied(node) if node.id.generation == id.generation => Some(node),
+            _ => None,
+        }
     }
 
     pub fn get_node_mut(&mut self, id: DeviceId) -> Option<&mut DeviceNode> {
@@ -243,7 +287,91 @@ impl DeviceTree {
         if current_gen != id.generation {
             return None;
         }
-        self.nodes.get_mut(id.index as usize)?.as_mut()
+        match self.nodes.get_mut(id.index as usize)? {
+            DeviceSlot::Occupied(node) => Some(node),
+            DeviceSlot::Free(_) => None,
+        }
+    }
+
+    pub fn iter_nodes(&self) -> impl Iterator<Item = &DeviceNode> {
+        self.nodes.iter().filter_map(|slot| match slot {
+            DeviceSlot::Occupied(node) => Some(node),
+            DeviceSlot::Free(_) => None,
+        })
+    }
+
+    /// Vacate the slot of `id` and push it onto the free list (LIFO).
+    /// The generation is bumped exactly once here so every outstanding handle
+    /// to the slot goes stale before it can be reused.
+    fn release_slot(&mut self, id: DeviceId) -> Option<DeviceNode> {
+        let compatible = self.get_node(id)?.desc.compatible.clone();
+        self.unindex_compatible(id, &compatible);
+        let idx = id.index as usize;
+        let slot = core::mem::replace(&mut self.nodes[idx], DeviceSlot::Free(self.free_head));
+        self.generations[idx] = self.generations[idx].wrapping_add(1);
+        self.free_head = Some(id.index);
+        match slot {
+            DeviceSlot::Occupied(node) => Some(node),
+            DeviceSlot::Free(_) => None,
+        }
+    }
+
+    /// Remove a leaf node, unlinking it from its parent. Its slot is recycled and
+    /// every outstanding `DeviceId` to it becomes stale.
+    pub fn remove(&mut self, id: DeviceId) -> Result<DeviceNode, Error> {
+        let node = self.get_node(id).ok_or(Error::NotFound)?;
+        if !node.children.is_empty() {
+            return Err(Error::InvalidArgs);
+        }
+        let parent = node.parent;
+
+        if let Some(pid) = parent {
+            if let Some(p_node) = self.get_node_mut(pid) {
+                p_node.children.retain(|c| *c != id);
+            }
+        }
+        if self.root == Some(id) {
+            self.root = None;
+        }
+        self.release_slot(id).ok_or(Error::NotFound)
+    }
+
+    /// Remove `id` and all of its descendants (post-order) and return the removed
+    /// ids so callers can tear down whatever referenced them. The root cannot be
+    /// removed this way.
+    pub fn remove_subtree(&mut self, id: DeviceId) -> Result<Vec<DeviceId>, Error> {
+        if !self.contains(id) {
+            return Err(Error::NotFound);
+        }
+        if self.root == Some(id) {
+            return Err(Error::InvalidArgs);
+        }
+
+        // Children are pushed after their parent, so reversing a pre-order walk
+        // gives an order in which every node is a leaf by the time it is removed.
+        let mut order = Vec::new();
+        let mut stack = alloc::vec![id];
+        while let Some(cur) = stack.pop() {
+            if let Some(node) = self.get_node(cur) {
+                stack.extend(node.children.iter().copied());
+                order.push(cur);
+            }
+        }
+
+        let mut removed = Vec::with_capacity(order.len());
+        for cur in order.into_iter().rev() {
+            self.remove(cur)?;
+            removed.push(cur);
+        }
+        Ok(removed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter_nodes().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter_nodes().next().is_none()
     }
 
     pub fn contains(&self, id: DeviceId) -> bool {
@@ -265,6 +393,7 @@ impl DeviceTree {
             // Print basic info: name, type, and status
             let status = match node.state {
                 DeviceState::Starting => "STARTING",
+                DeviceState::Initializing => "INITIALIZING",
                 DeviceState::Running => "RUNNING",
                 DeviceState::Ready => "READY",
                 DeviceState::Error => "ERROR",
@@ -273,7 +402,9 @@ impl DeviceTree {
                 DeviceSource::Unknown => "unknown",
                 DeviceSource::Dtb => "dtb",
                 DeviceSource::Acpi => "acpi",
-                DeviceSource::Runtime => "runtime",
+                DeviceSource::Pci => "pci",
+                DeviceSource::Report => "report",
+                DeviceSource::Synthetic => "synthetic",
             };
             let bus = match node.meta.bus {
                 DeviceBus::Unknown => "unknown",
@@ -348,6 +479,15 @@ impl DeviceTree {
         }
     }
 
+    fn parse_source(tags: &[String]) -> Option<DeviceSource> {
+        tags.iter().find_map(|tag| match tag.as_str() {
+            "src:dtb" => Some(DeviceSource::Dtb),
+            "src:acpi" => Some(DeviceSource::Acpi),
+            "src:pci" => Some(DeviceSource::Pci),
+            _ => None,
+        })
+    }
+
     fn apply_reported_meta(&mut self, id: DeviceId, meta: DeviceNodeMeta) -> Result<(), Error> {
         let node = self.get_node_mut(id).ok_or(Error::NotFound)?;
 
@@ -408,19 +548,48 @@ impl DeviceTree {
         Ok(())
     }
 
-    pub fn find_by_compatible(&self, compatible: &str) -> Vec<DeviceId> {
-        let mut out = Vec::new();
-        for node in self.nodes.iter().flatten() {
-            if node.desc.compatible.iter().any(|c| c == compatible) {
-                out.push(node.id);
+    fn index_compatible(&mut self, id: DeviceId, compatible: &[String]) {
+        for c in compatible {
+            let ids = self.compat_index.entry(normalize_compatible(c)).or_default();
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+
+    fn unindex_compatible(&mut self, id: DeviceId, compatible: &[String]) {
+        for c in compatible {
+            let key = normalize_compatible(c);
+            if let Some(ids) = self.compat_index.get_mut(&key) {
+                ids.retain(|i| *i != id);
+                if ids.is_empty() {
+                    self.compat_index.remove(&key);
+                }
             }
         }
-        out
+    }
+
+    /// Replace the compatible list of `id`, keeping the compatible index in sync.
+    /// `desc.compatible` must not be changed through `get_node_mut` directly.
+    pub fn set_compatible(&mut self, id: DeviceId, compatible: Vec<String>) -> Result<(), Error> {
+        let old = self.get_node(id).ok_or(Error::NotFound)?.desc.compatible.clone();
+        self.unindex_compatible(id, &old);
+        self.index_compatible(id, &compatible);
+        if let Some(node) = self.get_node_mut(id) {
+            node.desc.compatible = compatible;
+        }
+        Ok(())
+    }
+
+    /// Exact lookup through the compatible index, in insertion order. Both sides
+    /// are normalized, so `ARM,PL011` finds nodes listing `arm,pl011`.
+    pub fn find_by_compatible(&self, compatible: &str) -> Vec<DeviceId> {
+        self.compat_index.get(&normalize_compatible(compatible)).cloned().unwrap_or_default()
     }
 
     pub fn find_by_bus(&self, bus: DeviceBus) -> Vec<DeviceId> {
         let mut out = Vec::new();
-        for node in self.nodes.iter().flatten() {
+        for node in self.iter_nodes() {
             if node.meta.bus == bus {
                 out.push(node.id);
             }
@@ -445,6 +614,94 @@ impl DeviceTree {
         out
     }
 
+    /// Breadth-first ids of the subtree under `start`. A malformed subtree may
+    /// list a node twice or point back up; each node is visited once and the
+    /// walk never takes more steps than there are nodes.
+    pub fn walk(&self, start: DeviceId) -> Result<Vec<DeviceId>, Error> {
+        let mut queue = VecDeque::new();
+        let mut visited = BTreeSet::new();
+        let mut order = Vec::new();
+        queue.push_back(start);
+
+        let limit = self.len();
+        while let Some(id) = queue.pop_front() {
+            if !visited.insert(id) {
+                warn!("Device {} reached twice while scanning, skipping", id.index);
+                continue;
+            }
+            if visited.len() > limit {
+                error!("Scan from device {} exceeded {} nodes, aborting", start.index, limit);
+                return Err(Error::InvalidArgs);
+            }
+            if let Some(node) = self.get_node(id) {
+                queue.extend(node.children.iter().copied());
+                order.push(id);
+            }
+        }
+        Ok(order)
+    }
+
+    /// First node named `name`, breadth-first from the root.
+    pub fn find_by_name(&self, name: &str) -> Option<DeviceId> {
+        let root = self.root?;
+        self.walk(root)
+            .ok()?
+            .into_iter()
+            .find(|id| self.get_node(*id).is_some_and(|node| node.desc.name == name))
+    }
+
+    /// QUERY_DETAILED entry for node `name`. Physical nodes have no logical type.
+    pub fn summarize(&self, name: &str) -> Option<DeviceSummary> {
+        let node = self.get_node(self.find_by_name(name)?)?;
+        Some(DeviceSummary {
+            name: node.desc.name.clone(),
+            dev_type: None,
+            state: Some(node.state),
+            parent: node.parent.and_then(|p| self.get_node(p)).map(|p| p.desc.name.clone()),
+        })
+    }
+
+    /// Flat LIST_DEVICES view of the whole tree, root first.
+    pub fn list_entries(&self) -> Vec<DeviceListEntry> {
+        let Some(root) = self.root else {
+            return Vec::new();
+        };
+        self.collect_subtree_ir(root)
+            .into_iter()
+            .map(|ir| DeviceListEntry {
+                id: ir.id.index,
+                name: ir.name,
+                compatible: ir.compatible,
+                mmio_count: ir.meta.resources.mmio_count,
+                irq_count: ir.meta.resources.irq_count,
+                state: ir.state,
+            })
+            .collect()
+    }
+
+    /// DUMP_TREE snapshot: every node reachable from the root, in BFS order.
+    pub fn dump_entries(&self) -> Vec<TreeNodeEntry> {
+        let mut out = Vec::new();
+        let mut queue: VecDeque<DeviceId> = self.root.into_iter().collect();
+        while let Some(id) = queue.pop_front() {
+            let Some(ir) = self.to_ir_node(id) else {
+                continue;
+            };
+            queue.extend(ir.children.iter().copied());
+            out.push(TreeNodeEntry {
+                id: ir.id.index,
+                parent: ir.parent.map(|p| p.index),
+                name: ir.name,
+                compatible: ir.compatible,
+                source: ir.source,
+                state: ir.state,
+                mmio: ir.mmio,
+                irq: ir.irq,
+            });
+        }
+        out
+    }
+
     /// Mount a subtree reported by a driver under `mount_point`.
     /// `nodes` is a flattened list of nodes where `parent` is an index into `nodes`.
     /// If `parent` == usize::MAX, it attaches to `mount_point`.
@@ -457,24 +714,341 @@ impl DeviceTree {
             return Err(Error::InvalidArgs);
         }
 
+        // Firmware parsers keep the source of the platform they were started for,
+        // anything else a driver reports is tracked as such.
         let inherited_source =
-            self.get_node(mount_point).map(|n| n.source).unwrap_or(DeviceSource::Unknown);
+            match self.get_node(mount_point).map(|n| n.source).unwrap_or(DeviceSource::Unknown) {
+                source @ (DeviceSource::Dtb | DeviceSource::Acpi) => source,
+                _ => DeviceSource::Report,
+            };
+
+        // Validate the whole report before touching the tree: parents must point
+        // at an earlier node so a malformed report inserts nothing.
+        for (i, node_desc) in nodes.iter().enumerate() {
+            if node_desc.parent != usize::MAX && node_desc.parent >= i {
+                error!("Reported node {} has invalid parent index {}", i, node_desc.parent);
+                return Err(Error::InvalidArgs);
+            }
+            Self::validate_desc(&node_desc.desc)?;
+        }
 
         // Map from `nodes` index to real `DeviceId`
         let mut index_map: BTreeMap<usize, DeviceId> = BTreeMap::new();
+        let mut mounted = Vec::new();
 
         for (i, node_desc) in nodes.into_iter().enumerate() {
             let DeviceDescNode { parent, desc, meta } = node_desc;
-            let parent_id = if parent == usize::MAX {
-                mount_point
-            } else {
-                *index_map.get(&parent).ok_or(Error::InvalidArgs)?
-            };
-            let new_id = self.insert_with_source(Some(parent_id), desc, inherited_source)?;
-            self.apply_reported_meta(new_id, meta)?;
-            index_map.insert(i, new_id);
+            let parent_id = if parent == usize::MAX { mount_point } else { index_map[&parent] };
+            let source = Self::parse_source(&meta.tags).unwrap_or(inherited_source);
+            let inserted = self.insert_with_source(Some(parent_id), desc, source).and_then(|id| {
+                if parent == usize::MAX {
+                    mounted.push(id);
+                }
+                index_map.insert(i, id);
+                self.apply_reported_meta(id, meta)
+            });
+            if let Err(e) = inserted {
+                // Roll back what was mounted so far.
+                for id in mounted.into_iter().rev() {
+                    let _ = self.remove_subtree(id);
+                }
+                return Err(e);
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn desc(name: &str) -> DeviceDesc {
+        DeviceDesc {
+            name: name.to_string(),
+            compatible: alloc::vec![alloc::format!("test,{}", name)],
+            mmio: Vec::new(),
+            irq: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn free_list_reuses_every_slot_once() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let old: Vec<DeviceId> = (0..100)
+            .map(|i| tree.insert(Some(root), desc(&alloc::format!("dev{}", i))).unwrap())
+            .collect();
+        let slots = tree.nodes.len();
+
+        for id in old.iter() {
+            tree.remove(*id).unwrap();
+        }
+        let new: Vec<DeviceId> = (0..100)
+            .map(|i| tree.insert(Some(root), desc(&alloc::format!("new{}", i))).unwrap())
+            .collect();
+
+        assert_eq!(tree.nodes.len(), slots);
+        assert!(old.iter().all(|id| tree.get_node(*id).is_none()));
+        assert!(new.iter().all(|id| tree.get_node(*id).is_some()));
+        // LIFO reuse: the last freed slot comes back first, one generation later.
+        assert_eq!(new[0].index, old[99].index);
+        assert_eq!(new[0].generation, old[99].generation + 1);
+        assert_eq!(tree.get_node(root).unwrap().children.len(), 100);
+    }
+
+    #[test]
+    fn remove_subtree_takes_three_levels() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let bus = tree.insert(Some(root), desc("bus")).unwrap();
+        let keep = tree.insert(Some(root), desc("keep")).unwrap();
+        let dev = tree.insert(Some(bus), desc("dev")).unwrap();
+        let leaf_a = tree.insert(Some(dev), desc("leaf_a")).unwrap();
+        let leaf_b = tree.insert(Some(dev), desc("leaf_b")).unwrap();
+
+        let removed = tree.remove_subtree(bus).unwrap();
+
+        assert_eq!(removed.len(), 4);
+        // Post-order: every node comes after all of its descendants.
+        let pos = |id| removed.iter().position(|r| *r == id).unwrap();
+        assert!(pos(leaf_a) < pos(dev) && pos(leaf_b) < pos(dev));
+        assert!(pos(dev) < pos(bus));
+        assert!(removed.iter().all(|id| !tree.contains(*id)));
+        assert_eq!(tree.get_node(root).unwrap().children, alloc::vec![keep]);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn second_root_is_rejected() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        tree.insert(Some(root), desc("uart0")).unwrap();
+
+        assert!(matches!(tree.insert(None, desc("stray")), Err(Error::AlreadyExists)));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.root, Some(root));
+        assert!(tree.iter_nodes().all(|n| n.desc.name != "stray"));
+    }
+
+    #[test]
+    fn compatible_index_matches_a_full_scan() {
+        const COMPAT: [&str; 5] = ["arm,pl011", "ARM,PL011", "virtio,mmio", "ns16550a", "syscon"];
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let mut ids = Vec::new();
+        for i in 0..500 {
+            let mut d = desc(&alloc::format!("dev{}", i));
+            d.compatible = alloc::vec![COMPAT[i % 5].to_string(), COMPAT[(i / 5) % 5].to_string()];
+            ids.push(tree.insert(Some(root), d).unwrap());
+        }
+        // Churn: drop some nodes, rewrite others and reuse the freed slots.
+        for id in ids.iter().step_by(7) {
+            tree.remove(*id).unwrap();
+        }
+        for id in ids.iter().skip(3).step_by(11).filter(|id| tree.contains(**id)) {
+            tree.set_compatible(*id, alloc::vec!["syscon".to_string()]).unwrap();
+        }
+        for i in 0..40 {
+            let mut d = desc(&alloc::format!("late{}", i));
+            d.compatible = alloc::vec![COMPAT[i % 5].to_string()];
+            tree.insert(Some(root), d).unwrap();
+        }
+
+        for query in COMPAT.iter().chain(["test,root", "absent"].iter()) {
+            let mut indexed = tree.find_by_compatible(query);
+            let mut scanned: Vec<DeviceId> = tree
+                .iter_nodes()
+                .filter(|n| {
+                    n.desc
+                        .compatible
+                        .iter()
+                        .any(|c| normalize_compatible(c) == normalize_compatible(query))
+                })
+                .map(|n| n.id)
+                .collect();
+            indexed.sort();
+            scanned.sort();
+            assert_eq!(indexed, scanned, "lookup of {}", query);
+        }
+    }
+
+    #[test]
+    fn walk_survives_duplicate_and_back_edges() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let a = tree.insert(Some(root), desc("a")).unwrap();
+        let b = tree.insert(Some(a), desc("b")).unwrap();
+        // A buggy report listing `a` twice under root and `root` under `b`.
+        tree.get_node_mut(root).unwrap().children.push(a);
+        tree.get_node_mut(b).unwrap().children.push(root);
+
+        let order = tree.walk(root).unwrap();
+        assert_eq!(order, alloc::vec![root, a, b]);
+    }
+
+    #[test]
+    fn rescan_of_a_bus_stays_in_its_subtree() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let pci = tree.insert(Some(root), desc("pci0")).unwrap();
+        let usb = tree.insert(Some(root), desc("usb0")).unwrap();
+        let nic = tree.insert(Some(pci), desc("nic0")).unwrap();
+        let hub = tree.insert(Some(usb), desc("hub0")).unwrap();
+        for id in [root, pci, usb, nic] {
+            tree.get_node_mut(id).unwrap().state = DeviceState::Running;
+        }
+        // Hotplugged on pci0 while hub0 on the other bus still waits for a driver.
+        let new = tree.insert(Some(pci), desc("nvme0")).unwrap();
+
+        let bus = tree.find_by_name("pci0").unwrap();
+        let ready: Vec<_> = tree
+            .walk(bus)
+            .unwrap()
+            .into_iter()
+            .filter(|id| tree.get_node(*id).unwrap().state == DeviceState::Ready)
+            .collect();
+        assert_eq!(ready, alloc::vec![new]);
+        assert_eq!(tree.get_node(hub).unwrap().state, DeviceState::Ready);
+        assert_eq!(tree.find_by_name("pci9"), None);
+    }
+
+    #[test]
+    fn detailed_query_lists_the_same_names() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let bus = tree.insert(Some(root), desc("virtio")).unwrap();
+        tree.insert(Some(bus), desc("virtio0")).unwrap();
+        tree.insert(Some(bus), desc("virtio1")).unwrap();
+        tree.get_node_mut(bus).unwrap().state = DeviceState::Running;
+
+        let names: Vec<String> = tree.iter_nodes().map(|n| n.desc.name.clone()).collect();
+        let summaries: Vec<_> = names.iter().map(|n| tree.summarize(n).unwrap()).collect();
+        let bytes = postcard::to_allocvec(&summaries).unwrap();
+        let decoded: Vec<DeviceSummary> = postcard::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.iter().map(|s| s.name.clone()).collect::<Vec<_>>(), names);
+        assert!(decoded.iter().all(|s| s.dev_type.is_none()));
+        assert_eq!(decoded[0].parent, None);
+        assert_eq!(decoded[1].state, Some(DeviceState::Running));
+        assert_eq!(decoded[2].parent.as_deref(), Some("virtio"));
+        assert_eq!(decoded[3].state, Some(DeviceState::Ready));
+        assert!(tree.summarize("virtio9").is_none());
+    }
+
+    pub(crate) fn node(parent: usize, desc: DeviceDesc) -> DeviceDescNode {
+        DeviceDescNode { parent, desc, meta: DeviceNodeMeta::default() }
+    }
+
+    #[test]
+    fn mount_subtree_links_reported_parents() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let nodes =
+            alloc::vec![node(usize::MAX, desc("bus")), node(0, desc("dev")), node(1, desc("leaf"))];
+        tree.mount_subtree(root, nodes).unwrap();
+
+        let order = tree.walk(root).unwrap();
+        let names: Vec<&str> =
+            order.iter().map(|id| tree.get_node(*id).unwrap().desc.name.as_str()).collect();
+        assert_eq!(names, alloc::vec!["root", "bus", "dev", "leaf"]);
+        assert_eq!(tree.get_node(order[3]).unwrap().parent, Some(order[2]));
+    }
+
+    #[test]
+    fn mount_subtree_forward_parent_adds_nothing() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let nodes =
+            alloc::vec![node(usize::MAX, desc("bus")), node(2, desc("dev")), node(0, desc("late"))];
+
+        assert!(matches!(tree.mount_subtree(root, nodes), Err(Error::InvalidArgs)));
+        assert_eq!(tree.len(), 1);
+        assert!(tree.get_node(root).unwrap().children.is_empty());
+    }
+
+    #[test]
+    fn mount_subtree_bad_region_adds_nothing() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let mut bad = desc("bad");
+        bad.mmio.push(MMIORegion { base_addr: usize::MAX - 0xfff, size: 0x2000 });
+        let nodes = alloc::vec![node(usize::MAX, desc("bus")), node(0, bad)];
+
+        assert!(matches!(tree.mount_subtree(root, nodes), Err(Error::InvalidArgs)));
+        assert_eq!(tree.len(), 1);
+        assert!(tree.find_by_compatible("test,bus").is_empty());
+    }
+
+    #[test]
+    fn remove_subtree_refuses_root() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let child = tree.insert(Some(root), desc("child")).unwrap();
+        assert!(matches!(tree.remove_subtree(root), Err(Error::InvalidArgs)));
+        assert!(tree.contains(root) && tree.contains(child));
+
+        tree.remove_subtree(child).unwrap();
+        assert!(matches!(tree.remove_subtree(child), Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn list_entries_round_trip() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert(None, desc("root")).unwrap();
+        let uart = tree.insert(Some(root), desc("uart0")).unwrap();
+        tree.get_node_mut(uart).unwrap().state = DeviceState::Running;
+
+        let entries = tree.list_entries();
+        let bytes = postcard::to_allocvec(&entries).unwrap();
+        let decoded: Vec<DeviceListEntry> = postcard::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.len(), 2);
+        for (sent, got) in entries.iter().zip(decoded.iter()) {
+            assert_eq!(sent.id, got.id);
+            assert_eq!(sent.name, got.name);
+            assert_eq!(sent.compatible, got.compatible);
+            assert_eq!(sent.mmio_count, got.mmio_count);
+            assert_eq!(sent.irq_count, got.irq_count);
+            assert_eq!(sent.state, got.state);
+        }
+        assert_eq!(decoded[0].name, "root");
+        assert_eq!(decoded[1].name, "uart0");
+        assert_eq!(decoded[1].id, uart.index);
+        assert_eq!(decoded[1].state, DeviceState::Running);
+    }
+
+    #[test]
+    fn dump_round_trips_with_sources() {
+        let mut tree = DeviceTree::new();
+        let root = tree.insert_with_source(None, desc("root"), DeviceSource::Dtb).unwrap();
+        let mut uart = desc("uart0");
+        uart.mmio = alloc::vec![MMIORegion { base_addr: 0x1000_0000, size: 0x100 }];
+        uart.irq = alloc::vec![10];
+        let uart = tree.insert(Some(root), uart).unwrap();
+        let disk =
+            tree.insert_with_source(Some(root), desc("ramdisk"), DeviceSource::Synthetic).unwrap();
+        tree.get_node_mut(uart).unwrap().state = DeviceState::Running;
+
+        let entries = tree.dump_entries();
+        let bytes = postcard::to_allocvec(&entries).unwrap();
+        let decoded: Vec<TreeNodeEntry> = postcard::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].name, "root");
+        assert_eq!(decoded[0].parent, None);
+        assert_eq!(decoded[0].source, DeviceSource::Dtb);
+        // Children inherit the source of their parent unless given one.
+        assert_eq!(decoded[1].id, uart.index);
+        assert_eq!(decoded[1].parent, Some(root.index));
+        assert_eq!(decoded[1].source, DeviceSource::Dtb);
+        assert_eq!(decoded[1].state, DeviceState::Running);
+        assert_eq!(decoded[1].compatible, alloc::vec!["test,uart0".to_string()]);
+        assert_eq!(decoded[1].mmio[0].base_addr, 0x1000_0000);
+        assert_eq!(decoded[1].mmio[0].size, 0x100);
+        assert_eq!(decoded[1].irq, alloc::vec![10]);
+        assert_eq!(decoded[2].id, disk.index);
+        assert_eq!(decoded[2].source, DeviceSource::Synthetic);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::UnicornManager;
-use crate::layout::{BOOTINFO_ADDR, BOOTINFO_SLOT, MANIFEST_SLOT, RESOURCE_ADDR};
-use glenda::arch::mem::PGSIZE;
+use crate::config::Manifest;
+use crate::layout::{BOOTINFO_ADDR, BOOTINFO_SLOT, MANIFEST_SLOT};
+use crate::proto;
+use crate::unicorn::is_null_cap;
 use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint, Page, Reply};
 use glenda::error::Error;
 use glenda::interface::{
@@ -16,21 +18,13 @@ use glenda::protocol::{self, DEVICE_PROTO};
 impl<'a> SystemService for UnicornManager<'a> {
     fn init(&mut self) -> Result<(), Error> {
         log!("Loading config ...");
-        let (frame, size) =
-            self.res_client.get_config(Badge::null(), "drivers.json", MANIFEST_SLOT)?;
-        {
-            self.vspace_mgr.map_page(
-                frame,
-                RESOURCE_ADDR,
-                glenda::mem::Perms::READ | glenda::mem::Perms::WRITE,
-                (size + PGSIZE - 1) / PGSIZE,
-                self.res_client,
-                self.cspace_mgr,
-            )?;
+        self.config = Manifest::parse(&self.read_manifest()?)?;
+        if self.config.drivers.is_empty() {
+            warn!("drivers.json has 0 drivers configured, devices will stay unbound");
+        } else {
+            log!("{} drivers configured", self.config.drivers.len());
         }
-        let data = unsafe { core::slice::from_raw_parts(RESOURCE_ADDR as *const u8, size) };
-        self.config = serde_json::from_slice(data).map_err(|_| Error::InvalidConfig)?;
-        self.vspace_mgr.unmap(RESOURCE_ADDR, (size + PGSIZE - 1) / PGSIZE)?;
+        self.restore_enumeration();
 
         log!("Loading Bootinfo ...");
         let frame =
@@ -55,6 +49,16 @@ impl<'a> SystemService for UnicornManager<'a> {
     }
 
     fn listen(&mut self, ep: Endpoint, reply: CapPtr, recv: CapPtr) -> Result<(), Error> {
+        log!("Listening on {:?}: reply={:?}, recv={:?}", ep.cap(), reply, recv);
+        if is_null_cap(ep.cap()) || is_null_cap(reply) || is_null_cap(recv) {
+            error!("Listen slots must not be null");
+            return Err(Error::InvalidArgs);
+        }
+        // A shared slot would let incoming caps clobber the pending reply cap.
+        if reply.bits() == recv.bits() {
+            error!("Reply window and recv window share slot {:?}", reply);
+            return Err(Error::InvalidArgs);
+        }
         self.ipc.endpoint = ep;
         self.ipc.reply = Reply::from(reply);
         self.ipc.recv = recv;
@@ -81,6 +85,13 @@ impl<'a> SystemService for UnicornManager<'a> {
                 }
             }
             self.try_report_running();
+            self.run_due_work();
+            if self.shutting_down {
+                self.advance_shutdown();
+                if !self.ipc.running {
+                    break;
+                }
+            }
 
             let mut utcb = unsafe { UTCB::new() };
             utcb.clear();
@@ -169,7 +180,13 @@ impl<'a> SystemService for UnicornManager<'a> {
             (DEVICE_PROTO, device::GET_IRQ) => |s: &mut Self, u: &mut UTCB| {
                 handle_cap_call(u, |u| {
                     let id = u.get_mr(0) as usize;
-                    let handler = s.get_irq(badge, id, CapPtr::null())?;
+                    // Optional endpoint the driver wants IRQ notifications forwarded to.
+                    let notify = if u.get_msg_tag().flags().contains(MsgFlags::HAS_CAP) {
+                        s.ipc.recv
+                    } else {
+                        CapPtr::null()
+                    };
+                    let handler = s.get_irq(badge, id, notify)?;
                     Ok(handler.cap())
                 })
             },
@@ -190,8 +207,13 @@ impl<'a> SystemService for UnicornManager<'a> {
             },
             (DEVICE_PROTO, device::REGISTER_LOGIC) => |s: &mut Self, u: &mut UTCB| {
                 handle_call(u, |u| {
+                    let endpoint = if u.get_msg_tag().flags().contains(MsgFlags::HAS_CAP) {
+                        s.ipc.recv
+                    } else {
+                        CapPtr::null()
+                    };
                     let desc = unsafe { u.read_postcard()? };
-                    s.register_logic(badge, desc, s.ipc.recv)
+                    s.register_logic(badge, desc, endpoint)
                 })
             },
             (DEVICE_PROTO, device::ALLOC_LOGIC) => |s: &mut Self, u: &mut UTCB| {
@@ -209,6 +231,22 @@ impl<'a> SystemService for UnicornManager<'a> {
                     Ok(())
                 })
             },
+            (DEVICE_PROTO, proto::QUERY_DETAILED) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let query = unsafe { u.read_postcard()? };
+                    let devices = s.query_detailed(badge, query)?;
+                    unsafe { u.write_postcard(&devices)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::GET_ENUMERATION_MAP) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let map = s.logic_service.enumeration_map();
+                    u.set_mr(0, map.len());
+                    unsafe { u.write_postcard(&map)? };
+                    Ok(())
+                })
+            },
             (DEVICE_PROTO, device::GET_DESC) => |s: &mut Self, u: &mut UTCB| {
                 handle_buffer_call(u, |u| {
                     let name = unsafe { u.read_str()? };
@@ -221,11 +259,391 @@ impl<'a> SystemService for UnicornManager<'a> {
                 handle_buffer_call(u, |u| {
                     let name = unsafe { u.read_str()? };
                     let (id, desc) = s.get_logic_desc(badge, &name)?;
+                    // mr0 stays the raw id; mr1 adds the generation-checked
+                    // handle REMOVE_LOGIC takes, which is 0 for no device.
                     u.set_mr(0, id as usize);
+                    u.set_mr(1, s.logic_service.handle(id).map_or(0, |h| h.bits()));
+                    unsafe { u.write_postcard(&desc)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::SUBSCRIBE) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    if !u.get_msg_tag().flags().contains(MsgFlags::HAS_CAP) {
+                        return Err(Error::InvalidArgs);
+                    }
+                    let mask = u.get_mr(0);
+                    s.subscribe(badge, mask, s.ipc.recv)
+                })
+            },
+            (DEVICE_PROTO, proto::UNSUBSCRIBE) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |_| s.unsubscribe(badge))
+            },
+            (DEVICE_PROTO, proto::POLL_EVENTS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let max = u.get_mr(0);
+                    let events = s.poll_events(badge, max)?;
+                    u.set_mr(0, events.len());
+                    unsafe { u.write_postcard(&events)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::GET_LOGIC_COUNTERS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let counters = s.get_logic_counters(badge);
+                    unsafe { u.write_postcard(&counters)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::SEED_LOGIC_COUNTERS) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    let seed = unsafe { u.read_postcard()? };
+                    s.seed_logic_counters(badge, seed)
+                })
+            },
+            (DEVICE_PROTO, proto::LIST_IRQS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let irqs = s.list_irqs(badge);
+                    u.set_mr(0, irqs.len());
+                    unsafe { u.write_postcard(&irqs)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::GET_RESOURCE_MAP) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let map = s.get_resource_map(badge, &name)?;
+                    unsafe { u.write_postcard(&map)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::BIND_UNBOUND) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    let bound = s.bind_unbound(badge)?;
+                    u.set_mr(0, bound);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::GET_EVENT_LOG) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let entries = s.get_event_log(badge, u.get_mr(0));
+                    u.set_mr(0, entries.len());
+                    unsafe { u.write_postcard(&entries)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::LIST_THERMAL_ZONES) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let zones = s.list_thermal_zones(badge);
+                    u.set_mr(0, zones.len());
+                    unsafe { u.write_postcard(&zones)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::REPORT_THERMAL_ZONES) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let zones = unsafe { u.read_postcard()? };
+                    s.report_thermal_zones(badge, zones)
+                })
+            },
+            (DEVICE_PROTO, proto::GET_SOURCE) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let source = s.get_source(badge, &name)?;
+                    unsafe { u.write_postcard(&source)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::GET_STATUS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let status = s.get_status(badge);
+                    unsafe { u.write_postcard(&status)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::DETACH_LOGIC) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let notified = s.detach_logic(badge, &name)?;
+                    u.set_mr(0, notified);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::LIST_DEVICES_FOR_DRIVER) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let binary = unsafe { u.read_str()? };
+                    let devices = s.list_devices_for_driver(badge, &binary);
+                    u.set_mr(0, devices.len());
+                    unsafe { u.write_postcard(&devices)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::LIST_DEVICES) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let devices = s.list_devices(badge);
+                    u.set_mr(0, devices.len());
+                    unsafe { u.write_postcard(&devices)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::GET_DRIVER_ARGS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let args = s.get_driver_args(badge)?;
+                    u.set_mr(0, args.len());
+                    unsafe { u.write_postcard(&args)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::DRIVER_EXITED) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    let badge = u.get_badge();
+                    let pid = u.get_mr(0);
+                    s.driver_exited(badge, pid)?;
+                    s.try_report_running();
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::REPORT_BATTERY) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, status): (alloc::string::String, proto::BatteryStatus) =
+                        unsafe { u.read_postcard()? };
+                    s.report_battery(badge, &name, status)
+                })
+            },
+            (DEVICE_PROTO, proto::GET_BATTERY_STATUS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let status = s.get_battery_status(badge, &name)?;
+                    unsafe { u.write_postcard(&status)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::REPORT_GPIO) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, levels): (alloc::string::String, alloc::vec::Vec<bool>) =
+                        unsafe { u.read_postcard()? };
+                    s.report_gpio(badge, &name, levels)
+                })
+            },
+            (DEVICE_PROTO, proto::GPIO_GET) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let line = u.get_mr(0) as u32;
+                    let name = unsafe { u.read_str()? };
+                    let value = s.gpio_get(badge, &name, line)?;
+                    u.set_mr(0, value as usize);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::GPIO_SET) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let line = u.get_mr(0) as u32;
+                    let value = u.get_mr(1) != 0;
+                    let name = unsafe { u.read_str()? };
+                    s.gpio_set(badge, &name, line, value)
+                })
+            },
+            (DEVICE_PROTO, proto::TAKE_GPIO_WRITES) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let writes = s.take_gpio_writes(badge, &name)?;
+                    u.set_mr(0, writes.len());
+                    unsafe { u.write_postcard(&writes)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::DUMP_TREE) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let nodes = s.dump_tree(badge);
+                    u.set_mr(0, nodes.len());
+                    unsafe { u.write_postcard(&nodes)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::REMOVE_LOGIC) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| s.remove_logic(badge, u.get_mr(0)))
+            },
+            (DEVICE_PROTO, proto::RELOAD_MANIFEST) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    let queued = s.reload_manifest(badge)?;
+                    u.set_mr(0, queued);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::RESCAN_DEVICE) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    s.rescan_device(badge, &name)
+                })
+            },
+            (DEVICE_PROTO, proto::REPORT_NET_STATUS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, status): (alloc::string::String, proto::NetStatus) =
+                        unsafe { u.read_postcard()? };
+                    s.report_net_status(badge, &name, status)
+                })
+            },
+            (DEVICE_PROTO, proto::GET_NET_STATUS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let status = s.get_net_status(badge, &name)?;
+                    unsafe { u.write_postcard(&status)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::REPORT_BLOCK_RANGE) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, range): (alloc::string::String, proto::BlockRange) =
+                        unsafe { u.read_postcard()? };
+                    s.report_block_range(badge, &name, range)
+                })
+            },
+            (DEVICE_PROTO, proto::STAGE_LOGIC_IDENTITY) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let identity = unsafe { u.read_str()? };
+                    s.stage_logic_identity(badge, identity.into())
+                })
+            },
+            (DEVICE_PROTO, proto::IRQ_DONE) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| s.irq_done(badge, u.get_mr(0)))
+            },
+            (DEVICE_PROTO, proto::GET_LOGICAL_DESC) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let desc = s.get_logical_desc(badge, &name)?;
                     unsafe { u.write_postcard(&desc)? };
                     Ok(())
                 })
             },
+            (DEVICE_PROTO, proto::REPORT_FB_INFO) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, info): (alloc::string::String, proto::FbInfo) =
+                        unsafe { u.read_postcard()? };
+                    s.report_fb_info(badge, &name, info)
+                })
+            },
+            (DEVICE_PROTO, proto::GET_FB_INFO) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let info = s.get_fb_info(badge, &name)?;
+                    unsafe { u.write_postcard(&info)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::SET_PRIMARY_FB) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    s.set_primary_fb(badge, &name)
+                })
+            },
+            (DEVICE_PROTO, proto::SET_INPUT_FOCUS) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    if !u.get_msg_tag().flags().contains(MsgFlags::HAS_CAP) {
+                        return Err(Error::InvalidArgs);
+                    }
+                    let exclusive = u.get_mr(0) != 0;
+                    s.set_input_focus(badge, s.ipc.recv, exclusive)
+                })
+            },
+            (DEVICE_PROTO, proto::RELEASE_INPUT_FOCUS) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |_| s.release_input_focus(badge))
+            },
+            (DEVICE_PROTO, proto::PUSH_INPUT) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, events): (alloc::string::String, alloc::vec::Vec<proto::InputEvent>) =
+                        unsafe { u.read_postcard()? };
+                    let queued = s.push_input(badge, &name, events)?;
+                    u.set_mr(0, queued);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::POLL_INPUT) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let events = s.poll_input(badge, u.get_mr(0))?;
+                    u.set_mr(0, events.len());
+                    unsafe { u.write_postcard(&events)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::UART_CONFIGURE) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, config): (alloc::string::String, proto::UartConfig) =
+                        unsafe { u.read_postcard()? };
+                    s.uart_configure(badge, &name, config)
+                })
+            },
+            (DEVICE_PROTO, proto::UART_WRITE) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, data): (alloc::string::String, alloc::vec::Vec<u8>) =
+                        unsafe { u.read_postcard()? };
+                    let written = s.uart_write(badge, &name, &data)?;
+                    u.set_mr(0, written);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::UART_READ) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let max = u.get_mr(0);
+                    let name = unsafe { u.read_str()? };
+                    let data = s.uart_read(badge, &name, max)?;
+                    u.set_mr(0, data.len());
+                    unsafe { u.write_postcard(&data)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::UART_TAKE_TX) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let pending = s.uart_take_tx(badge, &name)?;
+                    unsafe { u.write_postcard(&pending)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::UART_PUSH_RX) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let (name, data): (alloc::string::String, alloc::vec::Vec<u8>) =
+                        unsafe { u.read_postcard()? };
+                    s.uart_push_rx(badge, &name, &data)
+                })
+            },
+            (DEVICE_PROTO, proto::ARM_ONESHOT) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    if !u.get_msg_tag().flags().contains(MsgFlags::HAS_CAP) {
+                        return Err(Error::InvalidArgs);
+                    }
+                    let deadline_ns = u.get_mr(0) as u64;
+                    let name = unsafe { u.read_str()? };
+                    let id = s.arm_oneshot(badge, &name, deadline_ns, s.ipc.recv)?;
+                    u.set_mr(0, id);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::ARM_PERIODIC) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| {
+                    if !u.get_msg_tag().flags().contains(MsgFlags::HAS_CAP) {
+                        return Err(Error::InvalidArgs);
+                    }
+                    let period_ns = u.get_mr(0) as u64;
+                    let name = unsafe { u.read_str()? };
+                    let id = s.arm_periodic(badge, &name, period_ns, s.ipc.recv)?;
+                    u.set_mr(0, id);
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::CANCEL_TIMER) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| s.cancel_timer(badge, u.get_mr(0)))
+            },
+            (DEVICE_PROTO, proto::TAKE_TIMER_COMMANDS) => |s: &mut Self, u: &mut UTCB| {
+                handle_buffer_call(u, |u| {
+                    let name = unsafe { u.read_str()? };
+                    let commands = s.take_timer_commands(badge, &name)?;
+                    u.set_mr(0, commands.len());
+                    unsafe { u.write_postcard(&commands)? };
+                    Ok(())
+                })
+            },
+            (DEVICE_PROTO, proto::TIMER_FIRED) => |s: &mut Self, u: &mut UTCB| {
+                handle_call(u, |u| s.timer_fired(badge, u.get_mr(0)))
+            },
             (_, _) => |_,_| Err(Error::InvalidMethod)
         }
     }
@@ -234,23 +652,81 @@ impl<'a> SystemService for UnicornManager<'a> {
         self.ipc.reply.reply(utcb)
     }
 
+    /// Drivers are stopped asynchronously; the run loop exits and reports
+    /// `Stopped` once `advance_shutdown` saw the last of them go.
     fn stop(&mut self) {
-        self.ipc.running = false;
-        self.init_client.report_service(Badge::null(), ServiceState::Stopped).unwrap_or_else(|e| {
-            error!("Failed to report stopped state: {:?}", e);
-        });
+        self.shutdown_all();
     }
 }
 
 impl<'a> UnicornManager<'a> {
-    pub fn handle_irq(&mut self, irq: usize) -> Result<(), Error> {
-        if let Some(&slot) = self.irq_caps.get(&irq) {
-            let handler = glenda::cap::IrqHandler::from(slot);
-            log!("IRQ {} received", irq);
-            handler.ack()?;
-        } else {
-            log!("Unknown IRQ notification: {}", irq);
+    /// Copy drivers.json out of the resource server's config frame.
+    fn read_manifest(&mut self) -> Result<alloc::vec::Vec<u8>, Error> {
+        let (frame, size) =
+            self.res_client.get_config(Badge::null(), "drivers.json", MANIFEST_SLOT)?;
+        let (vaddr, pages) =
+            self.map_transient(frame, size, glenda::mem::Perms::READ | glenda::mem::Perms::WRITE)?;
+        let data = unsafe { core::slice::from_raw_parts(vaddr as *const u8, size) }.to_vec();
+        self.unmap_transient(vaddr, pages)?;
+        Ok(data)
+    }
+
+    /// Pick up logical device names from enumeration.json when a previous boot
+    /// left one; without it devices are enumerated fresh.
+    fn restore_enumeration(&mut self) {
+        let slot = match self.alloc_slot() {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("No slot for enumeration.json: {:?}", e);
+                return;
+            }
+        };
+        let (frame, size) =
+            match self.res_client.get_config(Badge::null(), "enumeration.json", slot) {
+                Ok(config) => config,
+                Err(_) => {
+                    self.free_slot(slot);
+                    log!("No enumeration.json, enumerating logical devices fresh");
+                    return;
+                }
+            };
+        let map =
+            self.map_transient(frame, size, glenda::mem::Perms::READ).map(|(vaddr, pages)| {
+                let data = unsafe { core::slice::from_raw_parts(vaddr as *const u8, size) };
+                let map = serde_json::from_slice::<
+                    alloc::collections::BTreeMap<alloc::string::String, alloc::string::String>,
+                >(data);
+                let _ = self.unmap_transient(vaddr, pages);
+                map
+            });
+        self.free_slot(slot);
+        match map {
+            Ok(Ok(map)) => {
+                log!("Restored {} logical device names", map.len());
+                self.logic_service.restore_names(map);
+            }
+            Ok(Err(_)) => warn!("enumeration.json is malformed, enumerating fresh"),
+            Err(e) => warn!("Failed to map enumeration.json: {:?}", e),
         }
-        Ok(())
+    }
+
+    /// Re-read drivers.json and bind newly matching `Ready` nodes. Running
+    /// drivers keep going; a manifest that fails to parse leaves the old one in place.
+    /// Only the unbadged endpoint (init and the process manager) may reload.
+    pub fn reload_manifest(&mut self, badge: Badge) -> Result<usize, Error> {
+        if badge.bits() != Badge::null().bits() {
+            warn!("Client {} may not reload the manifest", badge);
+            return Err(Error::InvalidArgs);
+        }
+        let _ = CSPACE_CAP.delete(MANIFEST_SLOT);
+        let data = self.read_manifest()?;
+        let before = self.config.drivers.len();
+        self.config.reload(&data)?;
+        log!("Reloaded drivers.json: {} -> {} drivers", before, self.config.drivers.len());
+        // A new manifest may name binaries that failed before; give them another try.
+        self.missing_binaries.clear();
+        let queued = self.spawn_queue.len();
+        self.scan_platform(badge)?;
+        Ok(self.spawn_queue.len().saturating_sub(queued))
     }
 }
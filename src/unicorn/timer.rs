@@ -0,0 +1,320 @@
+use super::work::WORK_TICK_NS;
+use super::{UnicornManager, is_null_cap};
+use crate::proto::{NOTIFY_TIMER, TimerCommand};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use glenda::cap::{CSPACE_CAP, CapPtr, Endpoint};
+use glenda::error::Error;
+use glenda::ipc::Badge;
+use glenda::protocol::device::LogicDeviceType;
+
+// Armed timers a single consumer may hold at once.
+const MAX_TIMERS_PER_CONSUMER: usize = 16;
+
+pub struct ArmedTimer {
+    pub consumer: usize,
+    pub endpoint: CapPtr,
+    pub device: usize,
+    pub period_ns: Option<u64>,
+}
+
+/// Timers armed by consumers on timer devices. Arm and cancel requests are
+/// queued per device for its driver; expiries come back through `timer_fired`
+/// and are forwarded to the consumer with the timer id as badge.
+pub struct TimerService {
+    pub timers: BTreeMap<usize, ArmedTimer>, // timer_id -> timer
+    pub commands: BTreeMap<usize, Vec<TimerCommand>>, // logic_id -> queued for driver
+    pub heartbeat: Option<usize>,            // Unicorn's own work queue timer
+    next_id: usize,
+}
+
+impl TimerService {
+    pub fn new() -> Self {
+        Self { timers: BTreeMap::new(), commands: BTreeMap::new(), heartbeat: None, next_id: 1 }
+    }
+
+    fn insert(&mut self, timer: ArmedTimer, deadline_ns: u64) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.commands.entry(timer.device).or_default().push(TimerCommand::Arm {
+            id,
+            deadline_ns,
+            period_ns: timer.period_ns,
+        });
+        self.timers.insert(id, timer);
+        id
+    }
+
+    fn remove(&mut self, id: usize) -> Option<ArmedTimer> {
+        let timer = self.timers.remove(&id)?;
+        if self.heartbeat == Some(id) {
+            self.heartbeat = None;
+        }
+        if !is_null_cap(timer.endpoint) {
+            let _ = CSPACE_CAP.delete(timer.endpoint);
+        }
+        Some(timer)
+    }
+
+    pub fn take_commands(&mut self, device: usize) -> Vec<TimerCommand> {
+        self.commands.remove(&device).unwrap_or_default()
+    }
+
+    /// Forget every timer of a timer device that went away.
+    pub fn drop_device(&mut self, device: usize) {
+        let ids: Vec<usize> =
+            self.timers.iter().filter(|(_, t)| t.device == device).map(|(id, _)| *id).collect();
+        for id in ids {
+            self.remove(id);
+        }
+        self.commands.remove(&device);
+    }
+
+    /// Cancel every timer a consumer that went away still had armed. Returns
+    /// the devices whose driver has cancel requests waiting.
+    pub fn drop_consumer(&mut self, consumer: usize) -> BTreeSet<usize> {
+        let ids: Vec<usize> =
+            self.timers.iter().filter(|(_, t)| t.consumer == consumer).map(|(id, _)| *id).collect();
+        let mut devices = BTreeSet::new();
+        for id in ids {
+            if let Some(timer) = self.remove(id) {
+                self.commands.entry(timer.device).or_default().push(TimerCommand::Cancel { id });
+                devices.insert(timer.device);
+            }
+        }
+        devices
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    fn arm_timer(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        deadline_ns: u64,
+        period_ns: Option<u64>,
+        endpoint: CapPtr,
+    ) -> Result<usize, Error> {
+        let (device, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Timer {
+            return Err(Error::InvalidType);
+        }
+        let held =
+            self.timer_service.timers.values().filter(|t| t.consumer == badge.bits()).count();
+        if held >= MAX_TIMERS_PER_CONSUMER {
+            return Err(Error::OutOfMemory);
+        }
+        let ep = self.alloc_slot()?;
+        if let Err(e) = CSPACE_CAP.transfer_self(endpoint, ep) {
+            self.free_slot(ep);
+            return Err(e);
+        }
+
+        let timer = ArmedTimer { consumer: badge.bits(), endpoint: ep, device, period_ns };
+        let id = self.timer_service.insert(timer, deadline_ns);
+        self.kick_timer_driver(device, name);
+        Ok(id)
+    }
+
+    /// Timer device the heartbeat runs on: the armed one, else the first live one.
+    pub(super) fn heartbeat_device(&self) -> Option<(usize, String)> {
+        if let Some(timer) =
+            self.timer_service.heartbeat.and_then(|id| self.timer_service.timers.get(&id))
+        {
+            let (_, _, name) = self.logic_service.devices.get(&timer.device)?;
+            return Some((timer.device, name.clone()));
+        }
+        self.logic_service.devices.iter().find_map(|(id, (desc, _, name))| {
+            (desc.dev_type == LogicDeviceType::Timer
+                && !self.logic_service.detached.contains(id)
+                && self.logic_service.owners.contains_key(id))
+            .then(|| (*id, name.clone()))
+        })
+    }
+
+    /// Keep the heartbeat armed exactly while deferred work is waiting, so the
+    /// run loop is woken for it on an otherwise idle system.
+    pub(super) fn sync_heartbeat(&mut self) {
+        match self.timer_service.heartbeat {
+            None if !self.work_queue.is_empty() => {
+                let Some((device, name)) = self.heartbeat_device() else {
+                    return;
+                };
+                let timer = ArmedTimer {
+                    consumer: Badge::null().bits(),
+                    endpoint: CapPtr::null(),
+                    device,
+                    period_ns: Some(WORK_TICK_NS),
+                };
+                self.timer_service.heartbeat = Some(self.timer_service.insert(timer, 0));
+                self.kick_timer_driver(device, &name);
+            }
+            Some(id) if self.work_queue.is_empty() => {
+                if let Some(timer) = self.timer_service.remove(id) {
+                    let service = &mut self.timer_service;
+                    service
+                        .commands
+                        .entry(timer.device)
+                        .or_default()
+                        .push(TimerCommand::Cancel { id });
+                    if let Some((_, _, name)) = self.logic_service.devices.get(&timer.device) {
+                        self.kick_timer_driver(timer.device, name);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Cancel the timers of a client that went away and tell the drivers.
+    pub(super) fn drop_timer_consumer(&mut self, consumer: usize) {
+        for device in self.timer_service.drop_consumer(consumer) {
+            if let Some((_, _, name)) = self.logic_service.devices.get(&device) {
+                self.kick_timer_driver(device, name);
+            }
+        }
+    }
+
+    fn kick_timer_driver(&self, device: usize, name: &str) {
+        if let Some((_, ep, _)) = self.logic_service.devices.get(&device) {
+            if let Err(e) = Endpoint::from(*ep).notify(Badge::new(NOTIFY_TIMER)) {
+                warn!("Failed to notify timer driver of {}: {:?}", name, e);
+            }
+        }
+    }
+
+    pub fn arm_oneshot(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        deadline_ns: u64,
+        endpoint: CapPtr,
+    ) -> Result<usize, Error> {
+        self.arm_timer(badge, name, deadline_ns, None, endpoint)
+    }
+
+    /// The first expiry is one period from now, as seen by the timer driver.
+    pub fn arm_periodic(
+        &mut self,
+        badge: Badge,
+        name: &str,
+        period_ns: u64,
+        endpoint: CapPtr,
+    ) -> Result<usize, Error> {
+        if period_ns == 0 {
+            return Err(Error::InvalidArgs);
+        }
+        self.arm_timer(badge, name, 0, Some(period_ns), endpoint)
+    }
+
+    pub fn cancel_timer(&mut self, badge: Badge, id: usize) -> Result<(), Error> {
+        let service = &mut self.timer_service;
+        match service.timers.get(&id) {
+            _ if service.heartbeat == Some(id) => return Err(Error::InvalidArgs),
+            Some(timer) if timer.consumer == badge.bits() => {}
+            Some(_) => return Err(Error::InvalidArgs),
+            None => return Err(Error::NotFound),
+        }
+        let timer = service.remove(id).ok_or(Error::NotFound)?;
+        service.commands.entry(timer.device).or_default().push(TimerCommand::Cancel { id });
+        if let Some((_, _, name)) = self.logic_service.devices.get(&timer.device) {
+            self.kick_timer_driver(timer.device, name);
+        }
+        Ok(())
+    }
+
+    /// Driver side: arm/cancel requests queued for timer device `name`.
+    pub fn take_timer_commands(
+        &mut self,
+        badge: Badge,
+        name: &str,
+    ) -> Result<Vec<TimerCommand>, Error> {
+        let (device, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Timer {
+            return Err(Error::InvalidType);
+        }
+        if self.logic_service.owners.get(&device) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        Ok(self.timer_service.take_commands(device))
+    }
+
+    /// Driver side: timer `id` expired. One-shot timers are retired here.
+    pub fn timer_fired(&mut self, badge: Badge, id: usize) -> Result<(), Error> {
+        let timer = self.timer_service.timers.get(&id).ok_or(Error::NotFound)?;
+        if self.logic_service.owners.get(&timer.device) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        if self.timer_service.heartbeat == Some(id) {
+            self.work_queue.tick();
+            return Ok(());
+        }
+        if let Err(e) = Endpoint::from(timer.endpoint).notify(Badge::new(id)) {
+            warn!("Failed to deliver timer {} to {}: {:?}", id, timer.consumer, e);
+        }
+        if timer.period_ns.is_none() {
+            self.timer_service.remove(id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(consumer: usize, device: usize, period_ns: Option<u64>) -> ArmedTimer {
+        ArmedTimer { consumer, endpoint: CapPtr::null(), device, period_ns }
+    }
+
+    #[test]
+    fn arm_requests_reach_the_driver() {
+        let mut service = TimerService::new();
+        let oneshot = service.insert(timer(9, 3, None), 1_000);
+        let periodic = service.insert(timer(9, 3, Some(500)), 0);
+        service.insert(timer(9, 4, None), 7);
+
+        // What the timer driver of device 3 collects after NOTIFY_TIMER.
+        let forwarded = service.take_commands(3);
+        assert_eq!(
+            forwarded,
+            alloc::vec![
+                TimerCommand::Arm { id: oneshot, deadline_ns: 1_000, period_ns: None },
+                TimerCommand::Arm { id: periodic, deadline_ns: 0, period_ns: Some(500) },
+            ]
+        );
+        assert!(service.take_commands(3).is_empty());
+        assert_eq!(service.take_commands(4).len(), 1);
+        assert_eq!(service.timers.values().filter(|t| t.consumer == 9).count(), 3);
+    }
+
+    #[test]
+    fn dropping_a_device_forgets_its_timers() {
+        let mut service = TimerService::new();
+        let id = service.insert(timer(1, 3, Some(WORK_TICK_NS)), 0);
+        service.heartbeat = Some(id);
+        service.insert(timer(2, 5, None), 10);
+
+        service.drop_device(3);
+        assert!(service.heartbeat.is_none());
+        assert!(!service.timers.contains_key(&id));
+        assert!(service.take_commands(3).is_empty());
+        assert_eq!(service.timers.len(), 1);
+    }
+
+    #[test]
+    fn gone_consumer_has_its_timers_cancelled() {
+        let mut service = TimerService::new();
+        let a = service.insert(timer(7, 3, None), 10);
+        let b = service.insert(timer(7, 4, Some(500)), 0);
+        let kept = service.insert(timer(8, 3, None), 20);
+        service.take_commands(3);
+        service.take_commands(4);
+
+        assert_eq!(service.drop_consumer(7), BTreeSet::from([3, 4]));
+        assert_eq!(service.take_commands(3), alloc::vec![TimerCommand::Cancel { id: a }]);
+        assert_eq!(service.take_commands(4), alloc::vec![TimerCommand::Cancel { id: b }]);
+        assert_eq!(service.timers.keys().copied().collect::<Vec<_>>(), alloc::vec![kept]);
+        assert!(service.drop_consumer(7).is_empty());
+    }
+}
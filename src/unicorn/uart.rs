@@ -0,0 +1,217 @@
+use super::UnicornManager;
+use crate::proto::{NOTIFY_UART, UartConfig, UartParity};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use glenda::cap::Endpoint;
+use glenda::error::Error;
+use glenda::ipc::Badge;
+use glenda::protocol::device::LogicDeviceType;
+
+// Bytes buffered per direction; writes beyond it are cut short, old rx bytes dropped.
+const MAX_UART_BUFFER: usize = 4096;
+const MAX_BAUD: u32 = 4_000_000;
+
+/// Byte queues between clients and the driver of one uart. The driver drains
+/// `tx` (and a changed config) after `NOTIFY_UART`, and pushes into `rx`.
+pub struct UartPort {
+    pub config: UartConfig,
+    pub config_changed: bool,
+    pub tx: VecDeque<u8>,
+    pub rx: VecDeque<u8>,
+}
+
+impl UartPort {
+    fn new() -> Self {
+        Self {
+            config: UartConfig {
+                baud: 115200,
+                data_bits: 8,
+                parity: UartParity::None,
+                stop_bits: 1,
+            },
+            config_changed: false,
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+        }
+    }
+
+    fn configure(&mut self, config: UartConfig) -> Result<(), Error> {
+        if !config.is_valid() {
+            return Err(Error::InvalidArgs);
+        }
+        self.config = config;
+        self.config_changed = true;
+        Ok(())
+    }
+
+    /// Queue `data` for transmission. Returns how many bytes were accepted.
+    fn write(&mut self, data: &[u8]) -> usize {
+        let accepted = data.len().min(MAX_UART_BUFFER - self.tx.len());
+        self.tx.extend(&data[..accepted]);
+        accepted
+    }
+
+    fn read(&mut self, max: usize) -> Vec<u8> {
+        let count = if max == 0 { self.rx.len() } else { max.min(self.rx.len()) };
+        self.rx.drain(..count).collect()
+    }
+
+    fn take_tx(&mut self) -> (Option<UartConfig>, Vec<u8>) {
+        let config = core::mem::take(&mut self.config_changed).then_some(self.config);
+        (config, self.tx.drain(..).collect())
+    }
+
+    fn push_rx(&mut self, data: &[u8]) {
+        self.rx.extend(data);
+        let overflow = self.rx.len().saturating_sub(MAX_UART_BUFFER);
+        self.rx.drain(..overflow);
+    }
+}
+
+pub struct UartService {
+    pub ports: BTreeMap<usize, UartPort>, // logic_id -> port
+}
+
+impl UartService {
+    pub fn new() -> Self {
+        Self { ports: BTreeMap::new() }
+    }
+}
+
+impl UartConfig {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_BAUD).contains(&self.baud)
+            && (5..=8).contains(&self.data_bits)
+            && (1..=2).contains(&self.stop_bits)
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    fn uart_id(&self, name: &str) -> Result<usize, Error> {
+        let (id, desc) = self.logic_service.get_desc(name).ok_or(Error::NotFound)?;
+        if desc.dev_type != LogicDeviceType::Uart {
+            return Err(Error::InvalidType);
+        }
+        Ok(id)
+    }
+
+    fn kick_uart_driver(&self, id: usize, name: &str) {
+        if let Some((_, ep, _)) = self.logic_service.devices.get(&id) {
+            if let Err(e) = Endpoint::from(*ep).notify(Badge::new(NOTIFY_UART)) {
+                warn!("Failed to notify uart driver of {}: {:?}", name, e);
+            }
+        }
+    }
+
+    pub fn uart_configure(
+        &mut self,
+        _badge: Badge,
+        name: &str,
+        config: UartConfig,
+    ) -> Result<(), Error> {
+        let id = self.uart_id(name)?;
+        self.uart_service.ports.entry(id).or_insert_with(UartPort::new).configure(config)?;
+        self.kick_uart_driver(id, name);
+        Ok(())
+    }
+
+    /// Queue `data` for transmission. Returns how many bytes were accepted.
+    pub fn uart_write(&mut self, _badge: Badge, name: &str, data: &[u8]) -> Result<usize, Error> {
+        let id = self.uart_id(name)?;
+        let accepted = self.uart_service.ports.entry(id).or_insert_with(UartPort::new).write(data);
+        if accepted > 0 {
+            self.kick_uart_driver(id, name);
+        }
+        Ok(accepted)
+    }
+
+    pub fn uart_read(&mut self, _badge: Badge, name: &str, max: usize) -> Result<Vec<u8>, Error> {
+        let id = self.uart_id(name)?;
+        Ok(self.uart_service.ports.get_mut(&id).map(|port| port.read(max)).unwrap_or_default())
+    }
+
+    /// Driver side: pending config change and queued tx bytes.
+    pub fn uart_take_tx(
+        &mut self,
+        badge: Badge,
+        name: &str,
+    ) -> Result<(Option<UartConfig>, Vec<u8>), Error> {
+        let id = self.uart_id(name)?;
+        if self.logic_service.owners.get(&id) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        Ok(self.uart_service.ports.get_mut(&id).map(UartPort::take_tx).unwrap_or_default())
+    }
+
+    /// Driver side: bytes received on the line.
+    pub fn uart_push_rx(&mut self, badge: Badge, name: &str, data: &[u8]) -> Result<(), Error> {
+        let id = self.uart_id(name)?;
+        if self.logic_service.owners.get(&id) != Some(&badge.bits()) {
+            return Err(Error::InvalidArgs);
+        }
+        self.uart_service.ports.entry(id).or_insert_with(UartPort::new).push_rx(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(baud: u32, data_bits: u8, stop_bits: u8) -> UartConfig {
+        UartConfig { baud, data_bits, parity: UartParity::Even, stop_bits }
+    }
+
+    /// Stands in for a uart driver in loopback: applies config changes and
+    /// echoes every transmitted byte back as received.
+    struct FakeUartDriver {
+        config: Option<UartConfig>,
+    }
+
+    impl FakeUartDriver {
+        fn service(&mut self, port: &mut UartPort) {
+            let (config, tx) = port.take_tx();
+            if config.is_some() {
+                self.config = config;
+            }
+            port.push_rx(&tx);
+        }
+    }
+
+    #[test]
+    fn config_bounds() {
+        assert!(config(115200, 8, 1).is_valid());
+        assert!(config(MAX_BAUD, 5, 2).is_valid());
+        assert!(!config(0, 8, 1).is_valid());
+        assert!(!config(MAX_BAUD + 1, 8, 1).is_valid());
+        assert!(!config(9600, 4, 1).is_valid());
+        assert!(!config(9600, 9, 1).is_valid());
+        assert!(!config(9600, 8, 0).is_valid());
+        assert!(!config(9600, 8, 3).is_valid());
+    }
+
+    #[test]
+    fn configure_and_echo_through_driver() {
+        let mut port = UartPort::new();
+        let mut driver = FakeUartDriver { config: None };
+
+        assert!(matches!(port.configure(config(9600, 9, 1)), Err(Error::InvalidArgs)));
+        port.configure(config(9600, 7, 2)).unwrap();
+        assert_eq!(port.write(b"ping"), 4);
+        driver.service(&mut port);
+
+        assert_eq!(driver.config, Some(config(9600, 7, 2)));
+        assert!(!port.config_changed);
+        assert_eq!(port.read(2), b"pi");
+        assert_eq!(port.read(0), b"ng");
+        assert!(port.read(0).is_empty());
+    }
+
+    #[test]
+    fn writes_stop_at_the_buffer_limit() {
+        let mut port = UartPort::new();
+        let data = alloc::vec![0x55; MAX_UART_BUFFER + 10];
+        assert_eq!(port.write(&data), MAX_UART_BUFFER);
+        assert_eq!(port.write(b"x"), 0);
+    }
+}
@@ -0,0 +1,114 @@
+use super::UnicornManager;
+use crate::layout::{RESOURCE_ADDR, RESOURCE_WINDOW};
+use alloc::collections::BTreeMap;
+use glenda::arch::mem::PGSIZE;
+use glenda::cap::Page;
+use glenda::error::Error;
+use glenda::interface::VSpaceService;
+use glenda::mem::Perms;
+
+/// First-fit allocator for short-lived mappings (config files, reported frame
+/// caps) inside the resource window, so two of them never share a VA.
+pub struct TransientVa {
+    free: BTreeMap<usize, usize>, // base -> pages
+}
+
+impl TransientVa {
+    pub fn new(base: usize, size: usize) -> Self {
+        let mut free = BTreeMap::new();
+        free.insert(base, size / PGSIZE);
+        Self { free }
+    }
+
+    pub fn alloc(&mut self, pages: usize) -> Result<usize, Error> {
+        if pages == 0 {
+            return Err(Error::InvalidArgs);
+        }
+        let (&base, &len) =
+            self.free.iter().find(|(_, len)| **len >= pages).ok_or(Error::OutOfMemory)?;
+        self.free.remove(&base);
+        if len > pages {
+            self.free.insert(base + pages * PGSIZE, len - pages);
+        }
+        Ok(base)
+    }
+
+    /// Return a window, merging it with free neighbours on both sides.
+    pub fn free(&mut self, base: usize, pages: usize) {
+        let (mut base, mut pages) = (base, pages);
+        if let Some((&prev, &len)) = self.free.range(..base).next_back() {
+            if prev + len * PGSIZE == base {
+                self.free.remove(&prev);
+                base = prev;
+                pages += len;
+            }
+        }
+        if let Some(len) = self.free.remove(&(base + pages * PGSIZE)) {
+            pages += len;
+        }
+        self.free.insert(base, pages);
+    }
+}
+
+impl Default for TransientVa {
+    fn default() -> Self {
+        Self::new(RESOURCE_ADDR, RESOURCE_WINDOW)
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    /// Map `frame` at a fresh transient window covering `byte_len` bytes.
+    /// Returns the window base and its page count for `unmap_transient`.
+    pub(super) fn map_transient(
+        &mut self,
+        frame: Page,
+        byte_len: usize,
+        perms: Perms,
+    ) -> Result<(usize, usize), Error> {
+        let pages = (byte_len + PGSIZE - 1) / PGSIZE;
+        let vaddr = self.transient_va.alloc(pages)?;
+        if let Err(e) =
+            self.vspace_mgr.map_page(frame, vaddr, perms, pages, self.res_client, self.cspace_mgr)
+        {
+            self.transient_va.free(vaddr, pages);
+            return Err(e);
+        }
+        Ok((vaddr, pages))
+    }
+
+    pub(super) fn unmap_transient(&mut self, vaddr: usize, pages: usize) -> Result<(), Error> {
+        let res = self.vspace_mgr.unmap(vaddr, pages);
+        self.transient_va.free(vaddr, pages);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: usize = 0x4000_0000;
+
+    #[test]
+    fn live_windows_never_overlap() {
+        let mut va = TransientVa::new(BASE, 16 * PGSIZE);
+        let a = va.alloc(4).unwrap();
+        let b = va.alloc(2).unwrap();
+        assert!(a + 4 * PGSIZE <= b || b + 2 * PGSIZE <= a);
+        assert_eq!(va.alloc(11), Err(Error::OutOfMemory));
+        assert_eq!(va.alloc(0), Err(Error::InvalidArgs));
+    }
+
+    #[test]
+    fn freed_windows_are_reused_and_merged() {
+        let mut va = TransientVa::new(BASE, 8 * PGSIZE);
+        let a = va.alloc(4).unwrap();
+        let b = va.alloc(4).unwrap();
+        va.free(a, 4);
+        assert_eq!(va.alloc(4), Ok(a));
+
+        va.free(a, 4);
+        va.free(b, 4);
+        assert_eq!(va.alloc(8), Ok(BASE));
+    }
+}
@@ -0,0 +1,113 @@
+use super::UnicornManager;
+use super::platform::DeviceId;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use glenda::error::Error;
+
+// Upper bound of deferred tasks waiting at any time.
+const MAX_WORK_ITEMS: usize = 64;
+/// Length of one work queue tick: the period of Unicorn's heartbeat timer.
+pub const WORK_TICK_NS: u64 = 10_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkTask {
+    StartDriver(DeviceId),
+    RestartDriver(DeviceId),
+    RevokeLogic(usize),
+    ForceStop,
+    StartTimeout(usize, DeviceId),
+}
+
+struct WorkItem {
+    task: WorkTask,
+    due: usize,
+}
+
+/// Deferred work drained by `run` between two receives.
+///
+/// Deadlines are counted in ticks of `WORK_TICK_NS`. While tasks are waiting,
+/// Unicorn keeps a periodic heartbeat armed on a timer device; each expiry
+/// arrives as a TIMER_FIRED message, which wakes the run loop and advances the
+/// queue by one tick. Before any timer device exists every dispatch round
+/// counts as a tick instead. Tasks without a delay run before the next receive.
+pub struct WorkQueue {
+    items: VecDeque<WorkItem>,
+    now: usize,
+}
+
+impl WorkQueue {
+    pub fn new() -> Self {
+        Self { items: VecDeque::new(), now: 0 }
+    }
+
+    pub fn push(&mut self, task: WorkTask, delay: Option<usize>) -> Result<(), Error> {
+        if self.items.len() >= MAX_WORK_ITEMS {
+            warn!("Work queue full, dropping {:?}", task);
+            return Err(Error::OutOfMemory);
+        }
+        let due = self.now.saturating_add(delay.unwrap_or(0));
+        self.items.push_back(WorkItem { task, due });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn tick(&mut self) {
+        self.now = self.now.saturating_add(1);
+    }
+
+    /// Pop every task whose deadline has been reached, in queueing order.
+    pub(super) fn take_due(&mut self) -> Vec<WorkTask> {
+        let now = self.now;
+        let mut due = Vec::new();
+        self.items.retain(|item| {
+            if item.due <= now {
+                due.push(item.task);
+                return false;
+            }
+            true
+        });
+        due
+    }
+}
+
+impl<'a> UnicornManager<'a> {
+    /// Queue `task` to run after `delay` ticks.
+    pub fn defer(&mut self, task: WorkTask, delay: Option<usize>) -> Result<(), Error> {
+        self.work_queue.push(task, delay)
+    }
+
+    pub(super) fn run_due_work(&mut self) {
+        for task in self.work_queue.take_due() {
+            match task {
+                WorkTask::StartDriver(id) => {
+                    if let Err(e) = self.start_driver(id) {
+                        error!("Deferred start of device {} failed: {:?}", id.index, e);
+                    }
+                }
+                WorkTask::RestartDriver(id) => {
+                    if let Err(e) = self.restart_driver(id) {
+                        error!("Restart of device {} failed: {:?}", id.index, e);
+                    }
+                }
+                WorkTask::RevokeLogic(id) => self.revoke_logic(id),
+                WorkTask::ForceStop => self.force_stop(),
+                WorkTask::StartTimeout(pid, id) => self.start_timed_out(pid, id),
+            }
+        }
+        if self.timer_service.heartbeat.is_none() {
+            self.work_queue.tick();
+        }
+        self.sync_heartbeat();
+    }
+}